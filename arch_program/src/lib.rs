@@ -3,10 +3,12 @@ pub mod account {
     use std::cell::RefCell;
     use std::rc::Rc;
     use crate::pubkey::Pubkey;
+    use crate::utxo::UtxoMeta;
 
     #[derive(Debug)]
     pub struct AccountInfo<'a> {
         pub key: &'a Pubkey,
+        pub utxo: &'a UtxoMeta,
         pub is_signer: bool,
         pub is_writable: bool,
         pub lamports: Rc<RefCell<u64>>,
@@ -17,6 +19,52 @@ pub mod account {
     }
 }
 
+pub mod instruction {
+    use borsh::BorshSerialize;
+    use crate::pubkey::Pubkey;
+
+    /// An account an instruction reads or writes
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct AccountMeta {
+        pub pubkey: Pubkey,
+        pub is_signer: bool,
+        pub is_writable: bool,
+    }
+
+    impl AccountMeta {
+        pub fn new(pubkey: Pubkey, is_signer: bool) -> Self {
+            AccountMeta { pubkey, is_signer, is_writable: true }
+        }
+
+        pub fn new_readonly(pubkey: Pubkey, is_signer: bool) -> Self {
+            AccountMeta { pubkey, is_signer, is_writable: false }
+        }
+    }
+
+    /// A call into a program with the accounts it uses
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Instruction {
+        pub program_id: Pubkey,
+        pub accounts: Vec<AccountMeta>,
+        pub data: Vec<u8>,
+    }
+
+    impl Instruction {
+        /// Build an instruction whose data is the Borsh encoding of `data`
+        pub fn new_with_borsh<T: BorshSerialize>(
+            program_id: Pubkey,
+            data: &T,
+            accounts: Vec<AccountMeta>,
+        ) -> Self {
+            Instruction {
+                program_id,
+                accounts,
+                data: data.try_to_vec().expect("Instruction data should serialize"),
+            }
+        }
+    }
+}
+
 pub mod bitcoin {
     pub mod absolute {
         use thiserror::Error;
@@ -43,19 +91,194 @@ pub mod bitcoin {
             ONE,
             TWO,
         }
+
+        impl Version {
+            pub fn to_i32(&self) -> i32 {
+                match self {
+                    Version::ONE => 1,
+                    Version::TWO => 2,
+                }
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+    pub struct Txid(pub [u8; 32]);
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+    pub struct OutPoint {
+        pub txid: Txid,
+        pub vout: u32,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Sequence(pub u32);
+
+    impl Sequence {
+        pub const MAX: Self = Sequence(0xFFFFFFFF);
+        pub const ENABLE_RBF_NO_LOCKTIME: Self = Sequence(0xFFFFFFFD);
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct TxIn {
+        pub previous_output: OutPoint,
+        pub script_sig: Vec<u8>,
+        pub sequence: Sequence,
+        pub witness: Vec<Vec<u8>>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct TxOut {
+        pub value: u64,
+        pub script_pubkey: Vec<u8>,
     }
 
     #[derive(Debug, Clone)]
     pub struct Transaction {
         pub version: transaction::Version,
         pub lock_time: absolute::LockTime,
+        pub input: Vec<TxIn>,
+        pub output: Vec<TxOut>,
+    }
+
+    impl Transaction {
+        /// Size of the transaction without witness data, in bytes
+        pub fn base_size(&self) -> usize {
+            let inputs: usize = self
+                .input
+                .iter()
+                .map(|txin| 36 + varint_len(txin.script_sig.len()) + txin.script_sig.len() + 4)
+                .sum();
+            let outputs: usize = self
+                .output
+                .iter()
+                .map(|txout| 8 + varint_len(txout.script_pubkey.len()) + txout.script_pubkey.len())
+                .sum();
+
+            4 + varint_len(self.input.len()) + inputs + varint_len(self.output.len()) + outputs + 4
+        }
+
+        /// Size of the transaction including witness data, in bytes
+        pub fn total_size(&self) -> usize {
+            if self.input.iter().all(|txin| txin.witness.is_empty()) {
+                return self.base_size();
+            }
+
+            let witness: usize = self
+                .input
+                .iter()
+                .map(|txin| {
+                    varint_len(txin.witness.len())
+                        + txin
+                            .witness
+                            .iter()
+                            .map(|item| varint_len(item.len()) + item.len())
+                            .sum::<usize>()
+                })
+                .sum();
+
+            // Segwit marker and flag bytes
+            self.base_size() + 2 + witness
+        }
+
+        /// Transaction weight in weight units (BIP 141)
+        pub fn weight(&self) -> usize {
+            self.base_size() * 3 + self.total_size()
+        }
+
+        /// Virtual size in vbytes, rounded up
+        pub fn vsize(&self) -> usize {
+            self.weight().div_ceil(4)
+        }
+
+        /// Double SHA-256 of the serialization without witness data
+        pub fn compute_txid(&self) -> Txid {
+            use ::bitcoin::hashes::{sha256d, Hash};
+
+            let mut bytes = Vec::with_capacity(self.base_size());
+            bytes.extend_from_slice(&self.version.to_i32().to_le_bytes());
+            write_varint(&mut bytes, self.input.len());
+            for txin in &self.input {
+                bytes.extend_from_slice(&txin.previous_output.txid.0);
+                bytes.extend_from_slice(&txin.previous_output.vout.to_le_bytes());
+                write_varint(&mut bytes, txin.script_sig.len());
+                bytes.extend_from_slice(&txin.script_sig);
+                bytes.extend_from_slice(&txin.sequence.0.to_le_bytes());
+            }
+            write_varint(&mut bytes, self.output.len());
+            for txout in &self.output {
+                bytes.extend_from_slice(&txout.value.to_le_bytes());
+                write_varint(&mut bytes, txout.script_pubkey.len());
+                bytes.extend_from_slice(&txout.script_pubkey);
+            }
+            bytes.extend_from_slice(&self.lock_time.0.to_le_bytes());
+
+            Txid(sha256d::Hash::hash(&bytes).into_inner())
+        }
+    }
+
+    fn write_varint(bytes: &mut Vec<u8>, n: usize) {
+        match n {
+            0..=0xFC => bytes.push(n as u8),
+            0xFD..=0xFFFF => {
+                bytes.push(0xFD);
+                bytes.extend_from_slice(&(n as u16).to_le_bytes());
+            }
+            0x10000..=0xFFFFFFFF => {
+                bytes.push(0xFE);
+                bytes.extend_from_slice(&(n as u32).to_le_bytes());
+            }
+            _ => {
+                bytes.push(0xFF);
+                bytes.extend_from_slice(&(n as u64).to_le_bytes());
+            }
+        }
+    }
+
+    fn varint_len(n: usize) -> usize {
+        match n {
+            0..=0xFC => 1,
+            0xFD..=0xFFFF => 3,
+            0x10000..=0xFFFFFFFF => 5,
+            _ => 9,
+        }
+    }
+}
+
+pub mod utxo {
+    use crate::bitcoin::{OutPoint, Txid};
+
+    /// Reference to the Bitcoin UTXO backing an account
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct UtxoMeta {
+        pub txid: [u8; 32],
+        pub vout: u32,
+    }
+
+    impl UtxoMeta {
+        pub fn from_outpoint(outpoint: OutPoint) -> Self {
+            UtxoMeta {
+                txid: outpoint.txid.0,
+                vout: outpoint.vout,
+            }
+        }
+
+        pub fn to_outpoint(&self) -> OutPoint {
+            OutPoint {
+                txid: Txid(self.txid),
+                vout: self.vout,
+            }
+        }
     }
 }
 
 pub mod input_to_sign {
+    use crate::pubkey::Pubkey;
+
     #[derive(Debug, Clone)]
     pub struct InputToSign {
-        // Fields would be defined here in a real implementation
+        pub index: u32,
+        pub signer: Pubkey,
     }
 }
 
@@ -86,31 +309,216 @@ pub mod program_error {
         
         #[error("Not enough account keys")]
         NotEnoughAccountKeys,
+        
+        #[error("Invalid seeds for program address")]
+        InvalidSeeds,
+        
+        #[error("Missing required signature")]
+        MissingRequiredSignature,
+
+        #[error("Computational budget exceeded")]
+        ComputeBudgetExceeded,
     }
 }
 
+/// Compute unit costs charged by the simulated runtime
+///
+/// The runtime cannot meter the program's own code, so it charges for what
+/// grows with pool size and call count: the bytes a program is handed to
+/// deserialize, hashing, and syscalls.
+pub mod compute_budget {
+    /// Units an instruction may consume unless the runtime is given another limit
+    pub const DEFAULT_COMPUTE_UNIT_LIMIT: u64 = 200_000;
+    /// Per byte of instruction data and account data handed to the program
+    pub const DESERIALIZE_BYTE_COST: u64 = 1;
+    /// Per SHA-256 hash, plus `SHA256_BYTE_COST` per byte hashed
+    pub const SHA256_BASE_COST: u64 = 85;
+    pub const SHA256_BYTE_COST: u64 = 1;
+    /// Per `create_program_address`, including its hash and curve check
+    pub const CREATE_PROGRAM_ADDRESS_COST: u64 = 1500;
+    /// Per call into the runtime, such as `get_account_script_pubkey`
+    pub const SYSCALL_BASE_COST: u64 = 100;
+    /// Per `msg!` line or `emit!` event
+    pub const LOG_COST: u64 = 100;
+}
+
 pub mod pubkey {
     use std::fmt;
     use std::hash::Hash;
+    use std::str::FromStr;
     use borsh::{BorshSerialize, BorshDeserialize};
     use std::io::{Read, Write};
+    use thiserror::Error;
+    use crate::program_error::ProgramError;
+
+    /// Maximum length of a single program address seed
+    pub const MAX_SEED_LEN: usize = 32;
+    /// Maximum number of seeds for a program address
+    pub const MAX_SEEDS: usize = 16;
+
+    const PDA_MARKER: &[u8] = b"ProgramDerivedAddress";
 
-    #[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+    /// Length of a pubkey, in bytes
+    pub const PUBKEY_BYTES: usize = 32;
+
+    #[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
     pub struct Pubkey([u8; 32]);
 
+    #[derive(Error, Debug, Clone, PartialEq, Eq)]
+    pub enum ParsePubkeyError {
+        #[error("String is the wrong size")]
+        WrongSize,
+
+        #[error("Invalid base58 or hex string")]
+        Invalid,
+    }
+
     impl Pubkey {
+        /// A key no other call in this process returns, for tests
+        ///
+        /// The counter is written into the first 8 bytes, so keys only repeat
+        /// after 2^64 calls. It starts at 1, so no key is `Pubkey::default()`.
         pub fn new_unique() -> Self {
-            use std::sync::atomic::{AtomicU8, Ordering};
-            static COUNTER: AtomicU8 = AtomicU8::new(0);
+            use std::sync::atomic::{AtomicU64, Ordering};
+            static COUNTER: AtomicU64 = AtomicU64::new(1);
             let mut key = [0u8; 32];
-            key[0] = COUNTER.fetch_add(1, Ordering::Relaxed);
+            key[..8].copy_from_slice(&COUNTER.fetch_add(1, Ordering::Relaxed).to_le_bytes());
             Pubkey(key)
         }
+
+        pub const fn from_bytes(bytes: [u8; PUBKEY_BYTES]) -> Self {
+            Pubkey(bytes)
+        }
+
+        pub const fn to_bytes(self) -> [u8; PUBKEY_BYTES] {
+            self.0
+        }
+
+        /// Derive a program address from `seeds` and `program_id`
+        ///
+        /// Fails with `InvalidSeeds` if the seeds are too long or the hash is a
+        /// valid x-only public key, since someone could hold its private key.
+        pub fn create_program_address(
+            seeds: &[&[u8]],
+            program_id: &Pubkey,
+        ) -> Result<Pubkey, ProgramError> {
+            use ::bitcoin::hashes::{sha256, Hash, HashEngine};
+            use ::bitcoin::secp256k1::schnorrsig;
+
+            if seeds.len() > MAX_SEEDS || seeds.iter().any(|seed| seed.len() > MAX_SEED_LEN) {
+                return Err(ProgramError::InvalidSeeds);
+            }
+            // Running out of units here fails the instruction once the program
+            // returns, so a search over bumps is not cut short
+            let _ = crate::runtime::consume_compute_units(crate::compute_budget::CREATE_PROGRAM_ADDRESS_COST);
+
+            let mut engine = sha256::Hash::engine();
+            for seed in seeds {
+                engine.input(seed);
+            }
+            engine.input(&program_id.0);
+            engine.input(PDA_MARKER);
+            let hash = sha256::Hash::from_engine(engine).into_inner();
+
+            if schnorrsig::PublicKey::from_slice(&hash).is_ok() {
+                return Err(ProgramError::InvalidSeeds);
+            }
+
+            Ok(Pubkey(hash))
+        }
+
+        /// Find a valid program address and its bump seed, trying bumps from 255 down
+        pub fn find_program_address(seeds: &[&[u8]], program_id: &Pubkey) -> (Pubkey, u8) {
+            Self::try_find_program_address(seeds, program_id)
+                .expect("Unable to find a viable program address bump seed")
+        }
+
+        pub fn try_find_program_address(
+            seeds: &[&[u8]],
+            program_id: &Pubkey,
+        ) -> Option<(Pubkey, u8)> {
+            for bump in (0..=u8::MAX).rev() {
+                let bump_seed = [bump];
+                let mut seeds_with_bump = seeds.to_vec();
+                seeds_with_bump.push(&bump_seed);
+                if let Ok(address) = Self::create_program_address(&seeds_with_bump, program_id) {
+                    return Some((address, bump));
+                }
+            }
+            None
+        }
+    }
+
+    impl AsRef<[u8]> for Pubkey {
+        fn as_ref(&self) -> &[u8] {
+            &self.0
+        }
+    }
+
+    impl From<[u8; PUBKEY_BYTES]> for Pubkey {
+        fn from(bytes: [u8; PUBKEY_BYTES]) -> Self {
+            Pubkey(bytes)
+        }
+    }
+
+    // Pubkeys display in base58, and in hex with `{:x}`
+    impl fmt::Display for Pubkey {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(&bs58::encode(self.0).into_string())
+        }
     }
 
     impl fmt::Debug for Pubkey {
         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            write!(f, "Pubkey({:?})", &self.0[..])
+            write!(f, "Pubkey({})", self)
+        }
+    }
+
+    impl fmt::LowerHex for Pubkey {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            for byte in self.0 {
+                write!(f, "{:02x}", byte)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Parses base58, or 64 hex digits (which are never a 32-byte base58 key)
+    impl FromStr for Pubkey {
+        type Err = ParsePubkeyError;
+
+        fn from_str(text: &str) -> Result<Self, Self::Err> {
+            let mut bytes = [0u8; PUBKEY_BYTES];
+            if text.len() == 2 * PUBKEY_BYTES {
+                hex::decode_to_slice(text, &mut bytes).map_err(|_| ParsePubkeyError::Invalid)?;
+                return Ok(Pubkey(bytes));
+            }
+            // A 32-byte key is at most 44 base58 characters
+            if text.len() > 44 {
+                return Err(ParsePubkeyError::WrongSize);
+            }
+            let decoded = bs58::decode(text).into_vec().map_err(|_| ParsePubkeyError::Invalid)?;
+            if decoded.len() != PUBKEY_BYTES {
+                return Err(ParsePubkeyError::WrongSize);
+            }
+            bytes.copy_from_slice(&decoded);
+            Ok(Pubkey(bytes))
+        }
+    }
+
+    // Pubkeys are written in base58 in JSON and other text formats
+    impl serde::Serialize for Pubkey {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_str(self)
+        }
+    }
+
+    impl<'de> serde::Deserialize<'de> for Pubkey {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            use serde::de::Error;
+
+            let text = <String as serde::Deserialize>::deserialize(deserializer)?;
+            text.parse().map_err(|error| D::Error::custom(format!("invalid pubkey {}: {}", text, error)))
         }
     }
 
@@ -148,7 +556,10 @@ pub mod pubkey {
 
 pub mod program {
     use crate::account::AccountInfo;
+    use crate::bitcoin::{OutPoint, Txid};
+    use crate::compute_budget::{SHA256_BASE_COST, SHA256_BYTE_COST, SYSCALL_BASE_COST};
     use crate::program_error::ProgramError;
+    use crate::runtime;
 
     pub fn next_account_info<'a, 'b, I: Iterator<Item = &'a AccountInfo<'b>>>(
         iter: &mut I,
@@ -156,18 +567,81 @@ pub mod program {
         iter.next().ok_or(ProgramError::NotEnoughAccountKeys)
     }
 
-    pub fn get_account_script_pubkey(_address: &str) -> Result<Vec<u8>, ProgramError> {
-        // Mock implementation
-        Ok(vec![0; 32])
+    /// Taproot script pubkey for `address`, with a hash of the address as the
+    /// output key
+    pub fn get_account_script_pubkey(address: &str) -> Result<Vec<u8>, ProgramError> {
+        runtime::consume_compute_units(SYSCALL_BASE_COST)?;
+        let mut script = vec![0x51, 0x20]; // OP_1, push 32 bytes
+        script.extend_from_slice(&sha256(&[address.as_bytes()]));
+        Ok(script)
+    }
+
+    /// SHA-256 of the concatenation of `values`
+    ///
+    /// Running out of compute units fails the instruction once the program
+    /// returns.
+    pub fn sha256(values: &[&[u8]]) -> [u8; 32] {
+        use ::bitcoin::hashes::{sha256, Hash, HashEngine};
+
+        let bytes: usize = values.iter().map(|value| value.len()).sum();
+        let _ = runtime::consume_compute_units(SHA256_BASE_COST + SHA256_BYTE_COST * bytes as u64);
+        let mut engine = sha256::Hash::engine();
+        for value in values {
+            engine.input(value);
+        }
+        sha256::Hash::from_engine(engine).into_inner()
     }
 
     pub fn get_bitcoin_block_height() -> Result<u32, ProgramError> {
-        // Mock implementation
-        Ok(100000)
+        runtime::consume_compute_units(SYSCALL_BASE_COST)?;
+        Ok(runtime::block_height())
     }
 
-    pub fn set_transaction_to_sign(_transaction: crate::transaction_to_sign::TransactionToSign) -> Result<(), ProgramError> {
-        // Mock implementation
+    /// Current Unix timestamp of the runtime clock
+    pub fn get_unix_timestamp() -> Result<i64, ProgramError> {
+        runtime::consume_compute_units(SYSCALL_BASE_COST)?;
+        Ok(runtime::unix_timestamp())
+    }
+
+    /// Current network fee rate estimate in sat/vB
+    pub fn get_fee_rate() -> Result<u64, ProgramError> {
+        runtime::consume_compute_units(SYSCALL_BASE_COST)?;
+        Ok(runtime::fee_rate())
+    }
+
+    /// Number of blocks confirming `txid`, zero while it is unconfirmed
+    pub fn get_transaction_confirmations(txid: &Txid) -> Result<u32, ProgramError> {
+        runtime::consume_compute_units(SYSCALL_BASE_COST)?;
+        Ok(runtime::transaction_confirmations(txid))
+    }
+
+    /// Value of output `vout` of `txid`, None if it is spent or unknown
+    pub fn get_bitcoin_tx_output_value(txid: &Txid, vout: u32) -> Result<Option<u64>, ProgramError> {
+        runtime::consume_compute_units(SYSCALL_BASE_COST)?;
+        Ok(runtime::get_utxo(&OutPoint { txid: *txid, vout }).map(|output| output.value))
+    }
+
+    /// X-only key of the network, which signs for program accounts; outputs
+    /// locked to it stay spendable by the program
+    pub fn get_network_xonly_pubkey() -> Result<[u8; 32], ProgramError> {
+        runtime::consume_compute_units(SYSCALL_BASE_COST)?;
+        Ok(runtime::network_xonly_pubkey())
+    }
+
+    /// Compute units the current instruction has left
+    pub fn get_remaining_compute_units() -> u64 {
+        runtime::remaining_compute_units()
+    }
+
+    /// Set the confirmation depth reported for `txid` (test hook)
+    pub fn set_transaction_confirmations(txid: Txid, depth: u32) {
+        runtime::set_transaction_confirmations(txid, depth);
+    }
+
+    /// Hand a transaction to the runtime for signing and broadcast
+    pub fn set_transaction_to_sign(transaction: crate::transaction_to_sign::TransactionToSign) -> Result<(), ProgramError> {
+        runtime::consume_compute_units(SYSCALL_BASE_COST)?;
+        runtime::record_transaction(transaction);
         Ok(())
     }
 }
@@ -176,23 +650,640 @@ pub mod helper {
     use crate::account::AccountInfo;
     use crate::program_error::ProgramError;
     use crate::pubkey::Pubkey;
+    use crate::runtime;
     use borsh::BorshSerialize;
 
+    /// Record the program's new state, paid for by `payer`
     pub fn add_state_transition<T: BorshSerialize>(
-        _payer: &AccountInfo,
-        _program_id: &Pubkey,
-        _state: &T,
+        payer: &AccountInfo,
+        program_id: &Pubkey,
+        state: &T,
     ) -> Result<(), ProgramError> {
-        // Mock implementation
+        let data = state.try_to_vec().map_err(|_| ProgramError::InvalidInstructionData)?;
+        runtime::consume_compute_units(crate::compute_budget::SYSCALL_BASE_COST)?;
+        runtime::record_state_transition(*payer.key, *program_id, data);
         Ok(())
     }
 }
 
+/// In-memory simulation of the Arch runtime
+///
+/// Each thread has its own runtime, so tests running in parallel do not see
+/// each other's block height, clock, accounts or transactions. The `program`
+/// and `helper` functions read and write it; tests drive and inspect it
+/// through the functions here.
+pub mod runtime {
+    use borsh::{BorshDeserialize, BorshSerialize};
+    use std::cell::RefCell;
+    use std::collections::{BTreeMap, HashMap};
+    use crate::bitcoin::{OutPoint, Txid, TxOut};
+    use crate::compute_budget::{DEFAULT_COMPUTE_UNIT_LIMIT, LOG_COST};
+    use crate::program_error::ProgramError;
+    use crate::pubkey::Pubkey;
+    use crate::transaction_to_sign::TransactionToSign;
+    use crate::utxo::UtxoMeta;
+
+    /// Block height a fresh runtime starts at
+    pub const DEFAULT_BLOCK_HEIGHT: u32 = 100000;
+
+    /// Fee rate a fresh runtime reports, in sat/vB
+    pub const DEFAULT_FEE_RATE: u64 = 2;
+
+    /// Network key a fresh runtime reports: the x coordinate of the secp256k1
+    /// generator
+    pub const DEFAULT_NETWORK_XONLY_PUBKEY: [u8; 32] = [
+        0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce, 0x87, 0x0b, 0x07,
+        0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9, 0x59, 0xf2, 0x81, 0x5b, 0x16, 0xf8, 0x17, 0x98,
+    ];
+
+    /// An account held in the runtime's account store
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct Account {
+        pub owner: Pubkey,
+        pub data: Vec<u8>,
+        pub utxo: UtxoMeta,
+        pub lamports: u64,
+    }
+
+    /// State recorded by `helper::add_state_transition`
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct StateTransition {
+        pub payer: Pubkey,
+        pub program_id: Pubkey,
+        pub data: Vec<u8>,
+    }
+
+    #[derive(Clone)]
+    struct Runtime {
+        block_height: u32,
+        unix_timestamp: Option<i64>, // None follows the system clock
+        fee_rate: u64,
+        network_xonly_pubkey: [u8; 32],
+        accounts: BTreeMap<Pubkey, Account>,
+        transactions: Vec<TransactionToSign>,
+        state_transitions: Vec<StateTransition>,
+        utxos: HashMap<OutPoint, TxOut>,
+        mined_at: HashMap<Txid, u32>, // Block height each transaction confirmed in
+        invocations: Vec<Invocation>,
+        compute_unit_limit: u64,
+        meter: Option<ComputeMeter>, // Set while an invocation is running
+    }
+
+    /// Log buffer and compute usage of a program invocation
+    #[derive(Clone, Default)]
+    struct Invocation {
+        logs: Vec<Log>,
+        compute_units: u64,
+    }
+
+    #[derive(Clone, Copy)]
+    struct ComputeMeter {
+        consumed: u64,
+        exceeded: bool,
+    }
+
+    /// A program log entry
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Log {
+        /// A line written with `msg!`
+        Message(String),
+        /// A Borsh-encoded event written with `emit!`
+        Data(Vec<u8>),
+    }
+
+    /// Saved runtime state, see `snapshot`
+    pub struct Snapshot(Runtime);
+
+    impl Default for Runtime {
+        fn default() -> Self {
+            Runtime {
+                block_height: DEFAULT_BLOCK_HEIGHT,
+                unix_timestamp: None,
+                fee_rate: DEFAULT_FEE_RATE,
+                network_xonly_pubkey: DEFAULT_NETWORK_XONLY_PUBKEY,
+                accounts: BTreeMap::new(),
+                transactions: Vec::new(),
+                state_transitions: Vec::new(),
+                utxos: HashMap::new(),
+                mined_at: HashMap::new(),
+                invocations: Vec::new(),
+                compute_unit_limit: DEFAULT_COMPUTE_UNIT_LIMIT,
+                meter: None,
+            }
+        }
+    }
+
+    thread_local! {
+        static RUNTIME: RefCell<Runtime> = RefCell::new(Runtime::default());
+        static ECHO_LOGS: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+    }
+
+    fn with<R>(f: impl FnOnce(&mut Runtime) -> R) -> R {
+        RUNTIME.with(|runtime| f(&mut runtime.borrow_mut()))
+    }
+
+    /// Discard all simulated state and start again from the defaults
+    pub fn reset() {
+        with(|runtime| *runtime = Runtime::default());
+    }
+
+    /// Save the runtime state so a failed transaction can be rolled back
+    pub fn snapshot() -> Snapshot {
+        with(|runtime| Snapshot(runtime.clone()))
+    }
+
+    /// Roll back to `snapshot`, keeping the log lines written since but not
+    /// the events, which never happened
+    pub fn restore(snapshot: Snapshot) {
+        with(|runtime| {
+            let mut invocations = std::mem::take(&mut runtime.invocations);
+            let kept = snapshot.0.invocations.len();
+            *runtime = snapshot.0;
+            for invocation in invocations.iter_mut().skip(kept) {
+                invocation.logs.retain(|entry| matches!(entry, Log::Message(_)));
+            }
+            runtime.invocations = invocations;
+        });
+    }
+
+    /// Drop the events of the current invocation, which failed
+    pub fn discard_events() {
+        with(|runtime| {
+            if let Some(invocation) = runtime.invocations.last_mut() {
+                invocation.logs.retain(|entry| matches!(entry, Log::Message(_)));
+            }
+        });
+    }
+
+    /// Start a new log buffer and compute meter for a program invocation
+    pub fn begin_invocation() {
+        with(|runtime| {
+            runtime.invocations.push(Invocation::default());
+            runtime.meter = Some(ComputeMeter { consumed: 0, exceeded: false });
+        });
+    }
+
+    /// Stop metering the current invocation and log its usage, failing with
+    /// `ComputeBudgetExceeded` if it ran over its limit
+    pub fn end_invocation() -> Result<u64, ProgramError> {
+        let (meter, limit) = with(|runtime| (runtime.meter.take(), runtime.compute_unit_limit));
+        let Some(meter) = meter else {
+            return Ok(0);
+        };
+        with(|runtime| {
+            if let Some(invocation) = runtime.invocations.last_mut() {
+                invocation.compute_units = meter.consumed;
+            }
+        });
+        log(format!("Program consumed {} of {} compute units", meter.consumed, limit));
+        if meter.exceeded {
+            return Err(ProgramError::ComputeBudgetExceeded);
+        }
+        Ok(meter.consumed)
+    }
+
+    /// Charge `units` to the running invocation, failing once it is over its
+    /// limit; outside an invocation nothing is charged
+    pub fn consume_compute_units(units: u64) -> Result<(), ProgramError> {
+        with(|runtime| {
+            let limit = runtime.compute_unit_limit;
+            let Some(meter) = runtime.meter.as_mut() else {
+                return Ok(());
+            };
+            if meter.exceeded || meter.consumed + units > limit {
+                meter.consumed = limit;
+                meter.exceeded = true;
+                return Err(ProgramError::ComputeBudgetExceeded);
+            }
+            meter.consumed += units;
+            Ok(())
+        })
+    }
+
+    /// Units the running invocation has left, the full limit outside one
+    pub fn remaining_compute_units() -> u64 {
+        with(|runtime| runtime.compute_unit_limit - runtime.meter.map_or(0, |meter| meter.consumed))
+    }
+
+    pub fn compute_unit_limit() -> u64 {
+        with(|runtime| runtime.compute_unit_limit)
+    }
+
+    /// Set the compute units each instruction may consume
+    pub fn set_compute_unit_limit(units: u64) {
+        with(|runtime| runtime.compute_unit_limit = units);
+    }
+
+    /// Compute units consumed by each invocation, oldest first
+    pub fn compute_units() -> Vec<u64> {
+        with(|runtime| runtime.invocations.iter().map(|invocation| invocation.compute_units).collect())
+    }
+
+    /// Compute units consumed by the latest invocation
+    pub fn last_compute_units() -> u64 {
+        with(|runtime| runtime.invocations.last().map_or(0, |invocation| invocation.compute_units))
+    }
+
+    // Append to the current invocation's buffer, starting one if there is none
+    fn push_log(entry: Log) {
+        with(|runtime| {
+            if runtime.invocations.is_empty() {
+                runtime.invocations.push(Invocation::default());
+            }
+            runtime.invocations.last_mut().unwrap().logs.push(entry);
+        });
+    }
+
+    /// Append a line to the program log (`msg!`)
+    pub fn log(message: String) {
+        let _ = consume_compute_units(LOG_COST);
+        if ECHO_LOGS.with(|echo| echo.get()) {
+            println!("{}", message);
+        }
+        push_log(Log::Message(message));
+    }
+
+    /// Append a structured event to the program log (`emit!`)
+    pub fn emit<T: BorshSerialize + std::fmt::Debug>(event: &T) {
+        let _ = consume_compute_units(LOG_COST);
+        if ECHO_LOGS.with(|echo| echo.get()) {
+            println!("Event: {:?}", event);
+        }
+        let data = event.try_to_vec().expect("Event should serialize");
+        push_log(Log::Data(data));
+    }
+
+    /// Also print program logs to stdout as they are written, which is off by
+    /// default; unlike the rest of the runtime this survives `reset`
+    pub fn set_echo_logs(echo: bool) {
+        ECHO_LOGS.with(|cell| cell.set(echo));
+    }
+
+    /// Message lines of every invocation, oldest first
+    pub fn logs() -> Vec<String> {
+        with(|runtime| {
+            runtime
+                .invocations
+                .iter()
+                .flat_map(|invocation| &invocation.logs)
+                .filter_map(|entry| match entry {
+                    Log::Message(message) => Some(message.clone()),
+                    Log::Data(_) => None,
+                })
+                .collect()
+        })
+    }
+
+    /// Log buffer of each invocation, oldest first
+    pub fn invocation_logs() -> Vec<Vec<Log>> {
+        with(|runtime| runtime.invocations.iter().map(|invocation| invocation.logs.clone()).collect())
+    }
+
+    /// Log buffer of the latest invocation
+    pub fn last_invocation_logs() -> Vec<Log> {
+        with(|runtime| runtime.invocations.last().map(|invocation| invocation.logs.clone()).unwrap_or_default())
+    }
+
+    /// Events of every invocation that decode as `T`, oldest first
+    pub fn events<T: BorshDeserialize>() -> Vec<T> {
+        with(|runtime| {
+            runtime
+                .invocations
+                .iter()
+                .flat_map(|invocation| &invocation.logs)
+                .filter_map(|entry| match entry {
+                    Log::Data(data) => T::try_from_slice(data).ok(),
+                    Log::Message(_) => None,
+                })
+                .collect()
+        })
+    }
+
+    pub fn block_height() -> u32 {
+        with(|runtime| runtime.block_height)
+    }
+
+    pub fn set_block_height(height: u32) {
+        with(|runtime| runtime.block_height = height);
+    }
+
+    /// Mine `blocks` empty blocks, deepening every confirmed transaction
+    pub fn advance_blocks(blocks: u32) {
+        with(|runtime| runtime.block_height += blocks);
+    }
+
+    /// Runtime clock, following the system clock until `set_unix_timestamp`
+    pub fn unix_timestamp() -> i64 {
+        with(|runtime| runtime.unix_timestamp).unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs() as i64)
+                .unwrap_or(0)
+        })
+    }
+
+    /// Freeze the runtime clock at `timestamp`
+    pub fn set_unix_timestamp(timestamp: i64) {
+        with(|runtime| runtime.unix_timestamp = Some(timestamp));
+    }
+
+    /// Move the runtime clock forward, freezing it if it followed the system clock
+    pub fn advance_time(seconds: i64) {
+        let now = unix_timestamp();
+        set_unix_timestamp(now + seconds);
+    }
+
+    pub fn fee_rate() -> u64 {
+        with(|runtime| runtime.fee_rate)
+    }
+
+    pub fn set_fee_rate(fee_rate: u64) {
+        with(|runtime| runtime.fee_rate = fee_rate);
+    }
+
+    pub fn network_xonly_pubkey() -> [u8; 32] {
+        with(|runtime| runtime.network_xonly_pubkey)
+    }
+
+    pub fn set_network_xonly_pubkey(key: [u8; 32]) {
+        with(|runtime| runtime.network_xonly_pubkey = key);
+    }
+
+    pub fn set_account(key: Pubkey, account: Account) {
+        with(|runtime| runtime.accounts.insert(key, account));
+    }
+
+    pub fn get_account(key: &Pubkey) -> Option<Account> {
+        with(|runtime| runtime.accounts.get(key).cloned())
+    }
+
+    pub fn remove_account(key: &Pubkey) -> Option<Account> {
+        with(|runtime| runtime.accounts.remove(key))
+    }
+
+    /// Every stored account, ordered by key
+    pub fn accounts() -> Vec<(Pubkey, Account)> {
+        with(|runtime| runtime.accounts.iter().map(|(key, account)| (*key, account.clone())).collect())
+    }
+
+    /// Transactions handed to `set_transaction_to_sign`, oldest first
+    pub fn transactions_to_sign() -> Vec<TransactionToSign> {
+        with(|runtime| runtime.transactions.clone())
+    }
+
+    /// State transitions recorded by `add_state_transition`, oldest first
+    pub fn state_transitions() -> Vec<StateTransition> {
+        with(|runtime| runtime.state_transitions.clone())
+    }
+
+    /// Record a transaction to sign, spending its inputs from the UTXO set and
+    /// adding its outputs
+    pub fn record_transaction(transaction: TransactionToSign) {
+        with(|runtime| {
+            let txid = transaction.transaction.compute_txid();
+            for input in &transaction.transaction.input {
+                runtime.utxos.remove(&input.previous_output);
+            }
+            for (vout, output) in transaction.transaction.output.iter().enumerate() {
+                runtime.utxos.insert(OutPoint { txid, vout: vout as u32 }, output.clone());
+            }
+            runtime.transactions.push(transaction);
+        });
+    }
+
+    pub fn record_state_transition(payer: Pubkey, program_id: Pubkey, data: Vec<u8>) {
+        with(|runtime| runtime.state_transitions.push(StateTransition { payer, program_id, data }));
+    }
+
+    /// Add an unspent output, such as the UTXO funding a pool
+    pub fn add_utxo(outpoint: OutPoint, output: TxOut) {
+        with(|runtime| runtime.utxos.insert(outpoint, output));
+    }
+
+    pub fn get_utxo(outpoint: &OutPoint) -> Option<TxOut> {
+        with(|runtime| runtime.utxos.get(outpoint).cloned())
+    }
+
+    /// Every unspent output, in no particular order
+    pub fn utxos() -> Vec<(OutPoint, TxOut)> {
+        with(|runtime| runtime.utxos.iter().map(|(outpoint, output)| (*outpoint, output.clone())).collect())
+    }
+
+    /// Confirm `txid` in the current block
+    pub fn confirm_transaction(txid: Txid) {
+        set_transaction_confirmations(txid, 1);
+    }
+
+    /// Number of blocks confirming `txid`, zero while it is unconfirmed
+    pub fn transaction_confirmations(txid: &Txid) -> u32 {
+        with(|runtime| match runtime.mined_at.get(txid) {
+            Some(&height) if height <= runtime.block_height => runtime.block_height - height + 1,
+            _ => 0,
+        })
+    }
+
+    /// Make `txid` report `depth` confirmations at the current height
+    pub fn set_transaction_confirmations(txid: Txid, depth: u32) {
+        with(|runtime| {
+            if depth == 0 {
+                runtime.mined_at.remove(&txid);
+            } else {
+                let height = (runtime.block_height + 1).saturating_sub(depth);
+                runtime.mined_at.insert(txid, height);
+            }
+        });
+    }
+}
+
+/// Test harness running instructions against the simulated runtime
+///
+/// Accounts live in the runtime's account store. `process_transaction` runs
+/// a list of instructions atomically: if any fails, the runtime is rolled back
+/// to where it was before the first.
+pub mod program_test {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+    use borsh::BorshDeserialize;
+    use crate::account::AccountInfo;
+    use crate::bitcoin::{OutPoint, Txid, TxOut};
+    use crate::compute_budget::DESERIALIZE_BYTE_COST;
+    use crate::instruction::Instruction;
+    use crate::program_error::ProgramError;
+    use crate::pubkey::Pubkey;
+    use crate::runtime::{self, Account};
+    use crate::utxo::UtxoMeta;
+
+    /// A program's instruction processor
+    pub type ProcessInstruction = fn(&Pubkey, &[AccountInfo], &[u8]) -> Result<(), ProgramError>;
+
+    // Data and balance shared by every `AccountInfo` of the same account
+    type AccountCells = HashMap<Pubkey, (Rc<RefCell<Vec<u8>>>, Rc<RefCell<u64>>)>;
+
+    pub struct ProgramTest {
+        program_id: Pubkey,
+        process_instruction: ProcessInstruction,
+        next_utxo: u32,
+    }
+
+    impl ProgramTest {
+        /// Register `process_instruction` as `program_id` on a fresh runtime
+        pub fn new(program_id: Pubkey, process_instruction: ProcessInstruction) -> Self {
+            runtime::reset();
+            ProgramTest {
+                program_id,
+                process_instruction,
+                next_utxo: 0,
+            }
+        }
+
+        pub fn program_id(&self) -> Pubkey {
+            self.program_id
+        }
+
+        pub fn add_account(&mut self, key: Pubkey, account: Account) {
+            runtime::set_account(key, account);
+        }
+
+        /// Add an empty account owned by the program, such as a pool or record
+        /// account before its first instruction
+        pub fn add_program_account(&mut self, key: Pubkey) {
+            self.add_account(key, Account { owner: self.program_id, ..Account::default() });
+        }
+
+        /// Back `key` with a new UTXO of `value` satoshis, creating the account
+        /// if it does not exist
+        pub fn fund_account(&mut self, key: Pubkey, value: u64) -> OutPoint {
+            self.next_utxo += 1;
+            let mut txid = [0u8; 32];
+            txid[..4].copy_from_slice(&self.next_utxo.to_le_bytes());
+            let outpoint = OutPoint { txid: Txid(txid), vout: 0 };
+            runtime::add_utxo(outpoint, TxOut { value, script_pubkey: key.as_ref().to_vec() });
+
+            let mut account = runtime::get_account(&key).unwrap_or_default();
+            account.utxo = UtxoMeta::from_outpoint(outpoint);
+            account.lamports += value;
+            runtime::set_account(key, account);
+            outpoint
+        }
+
+        pub fn account(&self, key: &Pubkey) -> Option<Account> {
+            runtime::get_account(key)
+        }
+
+        /// Deserialize an account's data, `None` if it is missing, empty or invalid
+        pub fn account_data<T: BorshDeserialize>(&self, key: &Pubkey) -> Option<T> {
+            let account = runtime::get_account(key)?;
+            if account.data.is_empty() {
+                return None;
+            }
+            T::try_from_slice(&account.data).ok()
+        }
+
+        pub fn logs(&self) -> Vec<String> {
+            runtime::logs()
+        }
+
+        /// Events emitted by the program that decode as `T`
+        pub fn events<T: BorshDeserialize>(&self) -> Vec<T> {
+            runtime::events()
+        }
+
+        /// Compute units consumed by each instruction processed so far
+        pub fn compute_units(&self) -> Vec<u64> {
+            runtime::compute_units()
+        }
+
+        pub fn set_compute_unit_limit(&mut self, units: u64) {
+            runtime::set_compute_unit_limit(units);
+        }
+
+        /// Run `instructions` in order, keeping their changes only if all succeed
+        pub fn process_transaction(&mut self, instructions: &[Instruction]) -> Result<(), ProgramError> {
+            let snapshot = runtime::snapshot();
+            for instruction in instructions {
+                if let Err(error) = self.process_instruction(instruction) {
+                    runtime::log(format!("Transaction failed: {}", error));
+                    runtime::restore(snapshot);
+                    return Err(error);
+                }
+            }
+            Ok(())
+        }
+
+        fn process_instruction(&self, instruction: &Instruction) -> Result<(), ProgramError> {
+            if instruction.program_id != self.program_id {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+
+            // Accounts missing from the store start out empty; an account listed
+            // twice shares its data and balance
+            let accounts: Vec<(Pubkey, Account)> = instruction
+                .accounts
+                .iter()
+                .map(|meta| (meta.pubkey, runtime::get_account(&meta.pubkey).unwrap_or_default()))
+                .collect();
+            let mut cells = AccountCells::new();
+            for (key, account) in &accounts {
+                cells.entry(*key).or_insert_with(|| {
+                    (Rc::new(RefCell::new(account.data.clone())), Rc::new(RefCell::new(account.lamports)))
+                });
+            }
+
+            let infos: Vec<AccountInfo> = instruction
+                .accounts
+                .iter()
+                .zip(&accounts)
+                .map(|(meta, (key, account))| AccountInfo {
+                    key,
+                    utxo: &account.utxo,
+                    is_signer: meta.is_signer,
+                    is_writable: meta.is_writable,
+                    lamports: cells[key].1.clone(),
+                    data: cells[key].0.clone(),
+                    owner: &account.owner,
+                    executable: false,
+                    rent_epoch: 0,
+                })
+                .collect();
+
+            // The program is charged for every byte it is handed to deserialize
+            let input_bytes = instruction.data.len() + cells.values().map(|(data, _)| data.borrow().len()).sum::<usize>();
+            runtime::begin_invocation();
+            let result = runtime::consume_compute_units(DESERIALIZE_BYTE_COST * input_bytes as u64)
+                .and_then(|()| (self.process_instruction)(&self.program_id, &infos, &instruction.data));
+            let usage = runtime::end_invocation();
+            usage.and(result)?;
+
+            // Write back the accounts the instruction could change
+            for (meta, (key, account)) in instruction.accounts.iter().zip(&accounts) {
+                if meta.is_writable {
+                    let (data, lamports) = &cells[key];
+                    runtime::set_account(*key, Account {
+                        data: data.borrow().clone(),
+                        lamports: *lamports.borrow(),
+                        ..account.clone()
+                    });
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
 // Msg macro definition
 #[macro_export]
 macro_rules! msg {
     ($($arg:tt)*) => {
-        println!($($arg)*);
+        $crate::runtime::log(format!($($arg)*))
+    };
+}
+
+// Emit macro definition, logging a Borsh-serializable event
+#[macro_export]
+macro_rules! emit {
+    ($event:expr) => {
+        $crate::runtime::emit(&$event)
     };
 }
 
@@ -206,7 +1297,14 @@ macro_rules! entrypoint {
             accounts: &[$crate::account::AccountInfo],
             instruction_data: &[u8],
         ) -> Result<(), $crate::program_error::ProgramError> {
-            $process_instruction(program_id, accounts, instruction_data)
+            $crate::runtime::begin_invocation();
+            let result = $process_instruction(program_id, accounts, instruction_data);
+            let usage = $crate::runtime::end_invocation();
+            let result = usage.and(result);
+            if result.is_err() {
+                $crate::runtime::discard_events();
+            }
+            result
         }
     };
 }
@@ -0,0 +1,132 @@
+//! JSON representation of the contract's state types
+//!
+//! Every public state type serializes with serde. The representation is
+//! meant to be stable for API layers and dashboards:
+//!
+//! - Field names are the Rust field names, and enums are externally tagged
+//!   (`"ContributionPhase"`, `{ "CastVote": { "proposal_id": 1 } }`).
+//! - Pubkeys are base58 strings; hashes and txids are hex strings.
+//! - Satoshi amounts are numbers, or strings for consumers whose numbers
+//!   lose precision above 2^53 (see `AmountFormat`). Either form is accepted
+//!   when reading.
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::cell::Cell;
+
+/// How satoshi amounts are written
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AmountFormat {
+    #[default]
+    Number,
+    /// Decimal strings, such as `"5000"`
+    String,
+}
+
+thread_local! {
+    static AMOUNT_FORMAT: Cell<AmountFormat> = const { Cell::new(AmountFormat::Number) };
+}
+
+/// Serialize `value` to a JSON value, writing amounts as `amounts`
+pub fn to_value<T: Serialize>(value: &T, amounts: AmountFormat) -> serde_json::Result<serde_json::Value> {
+    with_amount_format(amounts, || serde_json::to_value(value))
+}
+
+/// Serialize `value` to indented JSON, writing amounts as `amounts`
+pub fn to_string_pretty<T: Serialize>(value: &T, amounts: AmountFormat) -> serde_json::Result<String> {
+    with_amount_format(amounts, || serde_json::to_string_pretty(value))
+}
+
+/// Deserialize a value written by `to_value` or `to_string_pretty` in either format
+pub fn from_str<T: DeserializeOwned>(json: &str) -> serde_json::Result<T> {
+    serde_json::from_str(json)
+}
+
+fn with_amount_format<R>(amounts: AmountFormat, f: impl FnOnce() -> R) -> R {
+    let previous = AMOUNT_FORMAT.with(|format| format.replace(amounts));
+    let result = f();
+    AMOUNT_FORMAT.with(|format| format.set(previous));
+    result
+}
+
+// Serde helper for satoshi amounts, written as `AMOUNT_FORMAT` says
+pub(crate) mod amount {
+    use super::{AmountFormat, AMOUNT_FORMAT};
+    use serde::{de, Deserializer, Serializer};
+    use std::fmt;
+
+    pub fn serialize<S: Serializer>(amount: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        match AMOUNT_FORMAT.with(|format| format.get()) {
+            AmountFormat::Number => serializer.serialize_u64(*amount),
+            AmountFormat::String => serializer.collect_str(amount),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        deserializer.deserialize_any(AmountVisitor)
+    }
+
+    struct AmountVisitor;
+
+    impl de::Visitor<'_> for AmountVisitor {
+        type Value = u64;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("an amount in satoshis, as a number or a decimal string")
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<u64, E> {
+            Ok(value)
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<u64, E> {
+            u64::try_from(value).map_err(|_| E::custom("amount is negative"))
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<u64, E> {
+            value.parse().map_err(|_| E::custom(format!("invalid amount {:?}", value)))
+        }
+    }
+}
+
+// Serde helpers writing 32-byte hashes and txids as hex strings
+pub(crate) mod hex_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 32], D::Error> {
+        decode::<D>(&String::deserialize(deserializer)?)
+    }
+
+    fn decode<'de, D: Deserializer<'de>>(text: &str) -> Result<[u8; 32], D::Error> {
+        let mut bytes = [0u8; 32];
+        hex::decode_to_slice(text, &mut bytes).map_err(D::Error::custom)?;
+        Ok(bytes)
+    }
+
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(bytes: &Option<[u8; 32]>, serializer: S) -> Result<S::Ok, S::Error> {
+            bytes.map(hex::encode).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<[u8; 32]>, D::Error> {
+            Option::<String>::deserialize(deserializer)?.map(|text| decode::<D>(&text)).transpose()
+        }
+    }
+
+    pub mod vec {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(bytes: &[[u8; 32]], serializer: S) -> Result<S::Ok, S::Error> {
+            bytes.iter().map(hex::encode).collect::<Vec<_>>().serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<[u8; 32]>, D::Error> {
+            Vec::<String>::deserialize(deserializer)?.iter().map(|text| decode::<D>(text)).collect()
+        }
+    }
+}
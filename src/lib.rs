@@ -1,24 +1,27 @@
 use arch_program::bitcoin::absolute;
 use arch_program::{
     account::AccountInfo,
-    bitcoin::{absolute::LockTime, transaction::Version, Transaction},
-    entrypoint,
+    bitcoin::{absolute::LockTime, transaction::Version, OutPoint, Sequence, Transaction, TxIn, TxOut, Txid},
+    emit, entrypoint,
     helper::add_state_transition,
     input_to_sign::InputToSign,
     msg,
     program::{
-        get_account_script_pubkey, get_bitcoin_block_height, next_account_info,
-        set_transaction_to_sign,
+        get_account_script_pubkey, get_bitcoin_block_height, get_bitcoin_tx_output_value, get_fee_rate,
+        get_network_xonly_pubkey, get_transaction_confirmations, get_unix_timestamp, next_account_info,
+        set_transaction_to_sign, sha256,
     },
     program_error::ProgramError,
     pubkey::Pubkey,
     transaction_to_sign::TransactionToSign,
 };
 use borsh::{BorshDeserialize, BorshSerialize};
-use std::collections::HashMap;
-use chrono::Utc;
 use std::io::{Read, Write};
 
+pub mod json;
+pub mod replay;
+pub mod simulation;
+
 /// Error types for the Arch Network contract
 #[derive(Debug, Clone)]
 pub enum ContractError {
@@ -39,6 +42,33 @@ pub enum ContractError {
     NoVotesCast,
     QuorumNotReached,
     TransferAlreadyExecuted,
+    FeeTooHigh,
+    NoPendingPayout,
+    FeeRateNotIncreased,
+    PayoutNotConfirmed,
+    BondsNotSettled,
+    ProposalLimitReached,
+    BondAlreadySettled,
+    NotProposer,
+    ProposalHasVotes,
+    ProposalWithdrawn,
+    ProposalPeriodEnded,
+    VotingNotStarted,
+    ProposalTooLarge,
+    InvalidWithdrawalAmount,
+    HardCapReached,
+    PoolFailed,
+    PoolNotFailed,
+    NotAllowlisted,
+    NotSponsor,
+    MatchBudgetReturned,
+    WrongDistribution,
+    AllocationTooHigh,
+    AllocationLimitReached,
+    NotPayoutAuthority,
+    NoRequiredConfirmations,
+    FeeRateTooLow,
+    InsufficientDeposit,
     ProgramError(ProgramError),
     LockTimeError,
     IoError(String),
@@ -50,7 +80,7 @@ impl From<ProgramError> for ContractError {
     }
 }
 
-impl From<bitcoin::absolute::LockTimeError> for ContractError {
+impl From<absolute::LockTimeError> for ContractError {
     fn from(_: absolute::LockTimeError) -> Self {
         ContractError::LockTimeError
     }
@@ -85,30 +115,178 @@ impl From<ContractError> for ProgramError {
             ContractError::ProgramError(e) => e,
             ContractError::LockTimeError => ProgramError::Custom(18),
             ContractError::IoError(_) => ProgramError::Custom(19),
+            ContractError::FeeTooHigh => ProgramError::Custom(20),
+            ContractError::NoPendingPayout => ProgramError::Custom(21),
+            ContractError::FeeRateNotIncreased => ProgramError::Custom(22),
+            ContractError::PayoutNotConfirmed => ProgramError::Custom(23),
+            ContractError::BondsNotSettled => ProgramError::Custom(24),
+            ContractError::ProposalLimitReached => ProgramError::Custom(25),
+            ContractError::BondAlreadySettled => ProgramError::Custom(26),
+            ContractError::NotProposer => ProgramError::Custom(27),
+            ContractError::ProposalHasVotes => ProgramError::Custom(28),
+            ContractError::ProposalWithdrawn => ProgramError::Custom(29),
+            ContractError::ProposalPeriodEnded => ProgramError::Custom(30),
+            ContractError::VotingNotStarted => ProgramError::Custom(31),
+            ContractError::ProposalTooLarge => ProgramError::Custom(32),
+            ContractError::InvalidWithdrawalAmount => ProgramError::Custom(33),
+            ContractError::HardCapReached => ProgramError::Custom(34),
+            ContractError::PoolFailed => ProgramError::Custom(35),
+            ContractError::PoolNotFailed => ProgramError::Custom(36),
+            ContractError::NotAllowlisted => ProgramError::Custom(37),
+            ContractError::NotSponsor => ProgramError::Custom(38),
+            ContractError::MatchBudgetReturned => ProgramError::Custom(39),
+            ContractError::WrongDistribution => ProgramError::Custom(40),
+            ContractError::AllocationTooHigh => ProgramError::Custom(41),
+            ContractError::AllocationLimitReached => ProgramError::Custom(42),
+            ContractError::NotPayoutAuthority => ProgramError::Custom(43),
+            ContractError::NoRequiredConfirmations => ProgramError::Custom(44),
+            ContractError::FeeRateTooLow => ProgramError::Custom(45),
+            ContractError::InsufficientDeposit => ProgramError::Custom(46),
         }
     }
 }
 
-/// Pool parameters
-#[derive(BorshSerialize, BorshDeserialize, Clone, Debug)]
+/// Pool parameters; fields left out of JSON default to zero or off, except
+/// for a single required confirmation
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 pub struct PoolParams {
+    #[serde(with = "json::amount")]
     pub min_contribution: u64,
+    #[serde(with = "json::amount")]
     pub max_contribution: u64,
     pub contribution_deadline: i64, // Unix timestamp
+    pub proposal_deadline: i64,     // Unix timestamp
     pub voting_deadline: i64,       // Unix timestamp
+    #[serde(with = "json::amount")]
     pub proposal_threshold: u64,
+    #[serde(with = "json::amount")]
     pub voting_threshold: u64,
     pub quorum_percentage: u8,
+    #[serde(with = "json::amount")]
+    pub max_fee: u64, // Satoshis, upper bound on the network fee of each transaction the pool signs, 0 for no cap
+    pub required_confirmations: u32, // Payout depth before completion, at least 1
+    #[serde(with = "json::amount")]
+    pub proposal_bond: u64, // Satoshis the proposer deposits per proposal on top of their contribution, 0 for none
+    pub bond_return_percentage: u8, // Vote share a proposal needs to get its bond back
+    pub max_proposals_per_proposer: u32, // 0 for no limit
+    #[serde(with = "json::amount")]
+    pub soft_cap: u64, // Pool fails and refunds if not raised by the contribution deadline, 0 for none
+    #[serde(with = "json::amount")]
+    pub hard_cap: u64, // Most the pool accepts in total, 0 for no limit
+    pub close_at_hard_cap: bool, // Close contributions as soon as the hard cap is reached, or a minimum contribution no longer fits and the soft cap is met
+    #[serde(with = "json::hex_bytes::option")]
+    pub allowlist_root: Option<[u8; 32]>, // Merkle root of allowed contributors, None for a public pool
+    #[serde(with = "json::amount")]
+    pub match_budget: u64, // Deposited by the sponsor at initialization, net of the deposit fee once deposited, 0 for no matching
+    pub match_ratio: u16,  // Percent of each contribution the sponsor matches, 100 for 1:1
+    #[serde(with = "json::amount")]
+    pub match_cap: u64,    // Most matched per contributor, 0 for no limit
+    pub distribution: Distribution,
+}
+
+impl Default for PoolParams {
+    fn default() -> Self {
+        Self {
+            min_contribution: 0,
+            max_contribution: 0,
+            contribution_deadline: 0,
+            proposal_deadline: 0,
+            voting_deadline: 0,
+            proposal_threshold: 0,
+            voting_threshold: 0,
+            quorum_percentage: 0,
+            max_fee: 0,
+            required_confirmations: 1,
+            proposal_bond: 0,
+            bond_return_percentage: 0,
+            max_proposals_per_proposer: 0,
+            soft_cap: 0,
+            hard_cap: 0,
+            close_at_hard_cap: false,
+            allowlist_root: None,
+            match_budget: 0,
+            match_ratio: 0,
+            match_cap: 0,
+            distribution: Distribution::default(),
+        }
+    }
+}
+
+/// How the pool is paid out
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Distribution {
+    /// Everything goes to the proposal with the most votes
+    #[default]
+    WinnerTakeAll,
+    /// Contributors fund proposals directly, and the rest of the pool is
+    /// matched in proportion to the square of the sum of square roots of
+    /// each proposal's contributions
+    Quadratic,
 }
 
 /// Proposal structure
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Proposal {
     pub id: u64,
     pub proposer: Pubkey,
     pub bitcoin_address: String,
-    pub description: String,
+    pub metadata: ProposalMetadata,
     pub votes: u64,
+    #[serde(with = "json::amount")]
+    pub bond: u64, // Deposit held until settled, net of the deposit fee, then 0
+    pub withdrawn: bool,
+    pub amendments: Vec<ProposalAmendment>, // Replaced versions, oldest first
+    #[serde(with = "json::amount")]
+    pub funding: u64,   // Contributions directed to this proposal (quadratic pools)
+    pub sqrt_sum: u64,  // Sum of the square roots of each funder's total
+}
+
+/// Maximum length of a proposal payout address, in bytes
+pub const MAX_BITCOIN_ADDRESS_LEN: usize = 90;
+
+/// Maximum length of a proposal title, in bytes
+pub const MAX_TITLE_LEN: usize = 64;
+
+/// Maximum length of a proposal summary, in bytes
+pub const MAX_SUMMARY_LEN: usize = 280;
+
+/// Maximum length of a proposal document URI, in bytes
+pub const MAX_URI_LEN: usize = 200;
+
+/// Maximum number of amendments kept on a proposal
+pub const MAX_AMENDMENTS: usize = 8;
+
+/// Proposal metadata; the full document lives off chain at `uri`
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ProposalMetadata {
+    pub title: String,
+    pub summary: String,
+    pub uri: String,
+    #[serde(with = "json::hex_bytes")]
+    pub content_hash: [u8; 32], // SHA-256 of the document at `uri`
+}
+
+impl ProposalMetadata {
+    /// Check the metadata fits within the on-chain length limits
+    pub fn validate(&self) -> Result<(), ContractError> {
+        if self.title.len() > MAX_TITLE_LEN
+            || self.summary.len() > MAX_SUMMARY_LEN
+            || self.uri.len() > MAX_URI_LEN
+        {
+            return Err(ContractError::ProposalTooLarge);
+        }
+        Ok(())
+    }
+}
+
+/// A version of a proposal replaced by `AmendProposal`
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ProposalAmendment {
+    pub bitcoin_address: String,
+    pub metadata: ProposalMetadata,
+    pub amended_at: i64, // Unix timestamp
 }
 
 // Implement BorshSerialize for Proposal
@@ -117,8 +295,13 @@ impl BorshSerialize for Proposal {
         self.id.serialize(writer)?;
         self.proposer.serialize(writer)?;
         self.bitcoin_address.serialize(writer)?;
-        self.description.serialize(writer)?;
+        self.metadata.serialize(writer)?;
         self.votes.serialize(writer)?;
+        self.bond.serialize(writer)?;
+        self.withdrawn.serialize(writer)?;
+        self.amendments.serialize(writer)?;
+        self.funding.serialize(writer)?;
+        self.sqrt_sum.serialize(writer)?;
         Ok(())
     }
 }
@@ -129,216 +312,313 @@ impl BorshDeserialize for Proposal {
         let id = u64::deserialize_reader(reader)?;
         let proposer = Pubkey::deserialize_reader(reader)?;
         let bitcoin_address = String::deserialize_reader(reader)?;
-        let description = String::deserialize_reader(reader)?;
+        let metadata = ProposalMetadata::deserialize_reader(reader)?;
         let votes = u64::deserialize_reader(reader)?;
+        let bond = u64::deserialize_reader(reader)?;
+        let withdrawn = bool::deserialize_reader(reader)?;
+        let amendments = Vec::<ProposalAmendment>::deserialize_reader(reader)?;
+        let funding = u64::deserialize_reader(reader)?;
+        let sqrt_sum = u64::deserialize_reader(reader)?;
 
         Ok(Proposal {
             id,
             proposer,
             bitcoin_address,
-            description,
+            metadata,
             votes,
+            bond,
+            withdrawn,
+            amendments,
+            funding,
+            sqrt_sum,
         })
     }
 }
 
 /// Pool state
-#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum PoolState {
     Uninitialized,
     ContributionPhase,
+    ProposalPhase,
     VotingPhase,
     ExecutionPhase,
+    AwaitingConfirmation, // See `Contract::pending_payout`
     Completed,
+    Failed, // Closed below the soft cap; contributors claim refunds
 }
 
-/// Contract state
-#[derive(Clone, Debug)]
-pub struct Contract {
-    pub state: PoolState,
-    pub params: Option<PoolParams>,
-    pub total_balance: u64,
-    pub contributions: HashMap<Pubkey, u64>,
-    pub proposals: HashMap<u64, Proposal>,
-    pub votes: HashMap<Pubkey, u64>, // contributor -> proposal_id
-    pub next_proposal_id: u64,
-    pub winning_proposal: Option<u64>,
-    pub transfer_executed: bool,
+/// Seed prefix of program-derived pool accounts
+pub const POOL_SEED: &[u8] = b"pool";
+
+/// Identity of a pool: its creator, a creator-chosen nonce and the address bump seed
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PoolId {
+    pub creator: Pubkey,
+    pub nonce: u64,
+    pub bump: u8,
 }
 
-// Custom serialization for HashMap<Pubkey, u64>
-fn serialize_pubkey_map<W: Write>(
-    map: &HashMap<Pubkey, u64>,
-    writer: &mut W,
-) -> std::io::Result<()> {
-    let len = map.len() as u32;
-    len.serialize(writer)?;
-    for (key, value) in map.iter() {
-        key.serialize(writer)?;
-        value.serialize(writer)?;
+impl PoolId {
+    /// Address of the pool account under `program_id`
+    pub fn address(&self, program_id: &Pubkey) -> Result<Pubkey, ProgramError> {
+        Pubkey::create_program_address(
+            &[POOL_SEED, self.creator.as_ref(), &self.nonce.to_le_bytes(), &[self.bump]],
+            program_id,
+        )
     }
-    Ok(())
 }
 
-// Custom deserialization for HashMap<Pubkey, u64>
-fn deserialize_pubkey_map(buf: &mut &[u8]) -> std::io::Result<HashMap<Pubkey, u64>> {
-    let len = u32::deserialize(buf)?;
-    let mut map = HashMap::new();
-    for _ in 0..len {
-        let key = Pubkey::deserialize(buf)?;
-        let value = u64::deserialize(buf)?;
-        map.insert(key, value);
-    }
-    Ok(map)
+/// Derive the account address of `creator`'s pool number `pool_nonce`
+pub fn find_pool_address(program_id: &Pubkey, creator: &Pubkey, pool_nonce: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[POOL_SEED, creator.as_ref(), &pool_nonce.to_le_bytes()],
+        program_id,
+    )
 }
 
-// Custom serialization for HashMap<u64, Proposal>
-fn serialize_proposal_map<W: Write>(
-    map: &HashMap<u64, Proposal>,
-    writer: &mut W,
-) -> std::io::Result<()> {
-    let len = map.len() as u32;
-    len.serialize(writer)?;
-    for (key, value) in map.iter() {
-        key.serialize(writer)?;
-        value.serialize(writer)?;
-    }
-    Ok(())
+/// Payout transaction handed to the runtime but not yet confirmed
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PendingPayout {
+    #[serde(with = "json::hex_bytes")]
+    pub txid: [u8; 32],
+    pub fee_rate: u64, // sat/vB
+    #[serde(with = "json::amount")]
+    pub fee: u64,
+    #[serde(with = "json::hex_bytes::vec")]
+    pub replaced: Vec<[u8; 32]>, // Txids of earlier fee bumps, oldest first; any of them may still confirm
+}
+
+/// Unspent output holding the pool's funds
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PoolUtxo {
+    #[serde(with = "json::hex_bytes")]
+    pub txid: [u8; 32],
+    pub vout: u32,
+}
+
+/// Refund transaction handed to the runtime by a partial withdrawal
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Refund {
+    #[serde(with = "json::hex_bytes")]
+    pub txid: [u8; 32],
+    #[serde(with = "json::amount")]
+    pub value: u64, // Paid to the contributor, net of the fee
+    #[serde(with = "json::amount")]
+    pub fee: u64,
+}
+
+/// Seed prefix of per-contributor record accounts
+pub const CONTRIBUTOR_SEED: &[u8] = b"contributor";
+
+/// Seed prefix of per-proposal accounts
+pub const PROPOSAL_SEED: &[u8] = b"proposal";
+
+/// Derive the record account address of `contributor` in the pool at `pool_address`
+pub fn find_contributor_address(
+    program_id: &Pubkey,
+    pool_address: &Pubkey,
+    contributor: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[CONTRIBUTOR_SEED, pool_address.as_ref(), contributor.as_ref()],
+        program_id,
+    )
+}
+
+/// Derive the account address of proposal `proposal_id` in the pool at `pool_address`
+pub fn find_proposal_address(
+    program_id: &Pubkey,
+    pool_address: &Pubkey,
+    proposal_id: u64,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[PROPOSAL_SEED, pool_address.as_ref(), &proposal_id.to_le_bytes()],
+        program_id,
+    )
+}
+
+/// Longest allowlist proof accepted, enough for 2^32 contributors
+pub const MAX_ALLOWLIST_PROOF_LEN: usize = 32;
+
+// Domain tags keeping allowlist leaves and inner nodes apart
+const ALLOWLIST_LEAF_TAG: u8 = 0x00;
+const ALLOWLIST_NODE_TAG: u8 = 0x01;
+
+/// Allowlist Merkle leaf of `contributor`
+pub fn allowlist_leaf(contributor: &Pubkey) -> [u8; 32] {
+    sha256(&[&[ALLOWLIST_LEAF_TAG], contributor.as_ref()])
+}
+
+// Parent of two allowlist nodes; children are hashed in sorted order so
+// proofs need no left/right flags
+fn allowlist_node(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let (left, right) = if a <= b { (a, b) } else { (b, a) };
+    sha256(&[&[ALLOWLIST_NODE_TAG], left, right])
+}
+
+// Hash one tree level into the next; an odd last node is carried up unchanged
+fn allowlist_level(nodes: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    nodes
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => allowlist_node(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
 }
 
-// Custom deserialization for HashMap<u64, Proposal>
-fn deserialize_proposal_map(buf: &mut &[u8]) -> std::io::Result<HashMap<u64, Proposal>> {
-    let len = u32::deserialize(buf)?;
-    let mut map = HashMap::new();
-    for _ in 0..len {
-        let key = u64::deserialize(buf)?;
-        let value = Proposal::deserialize(buf)?;
-        map.insert(key, value);
+/// Merkle root of an allowlist, for pool creators building `PoolParams`
+pub fn allowlist_root(contributors: &[Pubkey]) -> [u8; 32] {
+    let mut nodes: Vec<[u8; 32]> = contributors.iter().map(allowlist_leaf).collect();
+    if nodes.is_empty() {
+        return [0; 32];
     }
-    Ok(map)
+    while nodes.len() > 1 {
+        nodes = allowlist_level(&nodes);
+    }
+    nodes[0]
 }
 
-// Implement BorshSerialize for Contract
-impl BorshSerialize for Contract {
-    fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
-        self.state.serialize(writer)?;
-        
-        // Serialize Option<PoolParams>
-        match &self.params {
-            Some(params) => {
-                1u8.serialize(writer)?; // Some variant
-                params.serialize(writer)?;
-            }
-            None => {
-                0u8.serialize(writer)?; // None variant
-            }
-        }
-        
-        self.total_balance.serialize(writer)?;
-        
-        // Serialize HashMap<Pubkey, u64>
-        serialize_pubkey_map(&self.contributions, writer)?;
-        
-        // Serialize HashMap<u64, Proposal>
-        serialize_proposal_map(&self.proposals, writer)?;
-        
-        // Serialize HashMap<Pubkey, u64>
-        serialize_pubkey_map(&self.votes, writer)?;
-        
-        self.next_proposal_id.serialize(writer)?;
-        
-        // Serialize Option<u64>
-        match self.winning_proposal {
-            Some(id) => {
-                1u8.serialize(writer)?; // Some variant
-                id.serialize(writer)?;
-            }
-            None => {
-                0u8.serialize(writer)?; // None variant
-            }
+/// Proof that `contributors[index]` is on the allowlist
+pub fn allowlist_proof(contributors: &[Pubkey], index: usize) -> Vec<[u8; 32]> {
+    let mut nodes: Vec<[u8; 32]> = contributors.iter().map(allowlist_leaf).collect();
+    let mut index = index;
+    let mut proof = Vec::new();
+    while nodes.len() > 1 {
+        if let Some(sibling) = nodes.get(index ^ 1) {
+            proof.push(*sibling);
         }
-        
-        self.transfer_executed.serialize(writer)?;
-        
-        Ok(())
+        nodes = allowlist_level(&nodes);
+        index /= 2;
     }
+    proof
 }
 
-// Implement BorshDeserialize for Contract
-impl BorshDeserialize for Contract {
-    fn deserialize(buf: &mut &[u8]) -> std::io::Result<Self> {
-        let state = PoolState::deserialize(buf)?;
-        
-        // Deserialize Option<PoolParams>
-        let params = match u8::deserialize(buf)? {
-            0 => None,
-            1 => Some(PoolParams::deserialize(buf)?),
-            _ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid option tag")),
-        };
-        
-        let total_balance = u64::deserialize(buf)?;
-        
-        // Deserialize HashMap<Pubkey, u64>
-        let contributions = deserialize_pubkey_map(buf)?;
-        
-        // Deserialize HashMap<u64, Proposal>
-        let proposals = deserialize_proposal_map(buf)?;
-        
-        // Deserialize HashMap<Pubkey, u64>
-        let votes = deserialize_pubkey_map(buf)?;
-        
-        let next_proposal_id = u64::deserialize(buf)?;
-        
-        // Deserialize Option<u64>
-        let winning_proposal = match u8::deserialize(buf)? {
-            0 => None,
-            1 => Some(u64::deserialize(buf)?),
-            _ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid option tag")),
-        };
-        
-        let transfer_executed = bool::deserialize(buf)?;
-        
-        Ok(Contract {
-            state,
-            params,
-            total_balance,
-            contributions,
-            proposals,
-            votes,
-            next_proposal_id,
-            winning_proposal,
-            transfer_executed,
-        })
+/// Check that `proof` places `contributor` under the allowlist `root`
+pub fn verify_allowlist_proof(root: &[u8; 32], contributor: &Pubkey, proof: &[[u8; 32]]) -> bool {
+    if proof.len() > MAX_ALLOWLIST_PROOF_LEN {
+        return false;
     }
+    let computed = proof
+        .iter()
+        .fold(allowlist_leaf(contributor), |node, sibling| allowlist_node(&node, sibling));
+    computed == *root
+}
 
-    fn deserialize_reader<R: Read>(reader: &mut R) -> std::io::Result<Self> {
-        let mut buf = Vec::new();
-        reader.read_to_end(&mut buf)?;
-        let mut slice = buf.as_slice();
-        Self::deserialize(&mut slice)
+/// A contributor's stake and vote, stored in its own derived account
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ContributorRecord {
+    pub contributor: Pubkey,
+    #[serde(with = "json::amount")]
+    pub amount: u64,
+    pub vote: Option<u64>, // proposal_id
+    #[serde(with = "json::amount")]
+    pub bonded: u64,       // Bond deposits held for open proposals, kept apart from `amount`
+    pub proposals: u32,
+    #[serde(with = "json::amount")]
+    pub matched: u64,      // Sponsor match earned, kept apart from `amount`
+    pub allocations: Vec<Allocation>, // Part of `amount` directed to proposals (quadratic pools)
+}
+
+/// Maximum number of proposals one contributor can fund in a quadratic pool
+pub const MAX_ALLOCATIONS: usize = 8;
+
+/// Part of a contribution directed to a proposal
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Allocation {
+    pub proposal_id: u64,
+    #[serde(with = "json::amount")]
+    pub amount: u64,
+}
+
+impl ContributorRecord {
+    /// Empty record for a contributor who has not contributed yet
+    pub fn new(contributor: Pubkey) -> Self {
+        Self {
+            contributor,
+            amount: 0,
+            vote: None,
+            bonded: 0,
+            proposals: 0,
+            matched: 0,
+            allocations: Vec::new(),
+        }
+    }
+    
+    /// Total directed to proposals so far
+    pub fn allocated(&self) -> u64 {
+        self.allocations.iter().map(|allocation| allocation.amount).sum()
     }
 }
 
+/// Contract state
+///
+/// The pool account only holds aggregates; contributions, votes and proposals
+/// live in their own derived accounts (`ContributorRecord` and `Proposal`).
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Contract {
+    pub state: PoolState,
+    pub params: Option<PoolParams>,
+    pub pool: PoolId,
+    #[serde(with = "json::amount")]
+    pub total_balance: u64,
+    pub total_contributors: u64,
+    pub total_votes: u64,
+    pub next_proposal_id: u64,
+    pub leading_proposal: Option<u64>,
+    pub leading_votes: u64,
+    pub winning_proposal: Option<u64>,
+    pub transfer_executed: bool,
+    pub pending_payout: Option<PendingPayout>,
+    #[serde(with = "json::amount")]
+    pub slashed_bonds: u64, // Added to the payout
+    #[serde(with = "json::amount")]
+    pub total_bonds: u64, // Unsettled bond deposits, held in the pool until settled
+    pub contributions_closed_at: Option<i64>, // Unix timestamp
+    pub sponsor: Option<Pubkey>,
+    #[serde(with = "json::amount")]
+    pub total_matched: u64,
+    pub match_returned: bool, // Unused match budget has been sent back to the sponsor
+    pub pool_utxo: Option<PoolUtxo>, // Change of the last refund, None while the funds are in the account's UTXO
+    pub spent_account_utxo: Option<PoolUtxo>, // Account UTXO spent by a refund, left out of later transactions
+}
+
 impl Default for Contract {
     fn default() -> Self {
         Self {
             state: PoolState::Uninitialized,
             params: None,
+            pool: PoolId::default(),
             total_balance: 0,
-            contributions: HashMap::new(),
-            proposals: HashMap::new(),
-            votes: HashMap::new(),
+            total_contributors: 0,
+            total_votes: 0,
             next_proposal_id: 1,
+            leading_proposal: None,
+            leading_votes: 0,
             winning_proposal: None,
             transfer_executed: false,
+            pending_payout: None,
+            slashed_bonds: 0,
+            total_bonds: 0,
+            contributions_closed_at: None,
+            sponsor: None,
+            total_matched: 0,
+            match_returned: false,
+            pool_utxo: None,
+            spent_account_utxo: None,
         }
     }
 }
 
 impl Contract {
     /// Initialize a new pool with the given parameters
+    ///
+    /// A pool with a match budget only opens for contributions once its
+    /// sponsor deposits the budget with `deposit_match_budget`.
     pub fn initialize_pool(&mut self, params: PoolParams) -> Result<(), ContractError> {
-        if self.state != PoolState::Uninitialized {
+        if self.state != PoolState::Uninitialized || self.params.is_some() {
             return Err(ContractError::PoolAlreadyInitialized);
         }
         
@@ -347,34 +627,100 @@ impl Contract {
             return Err(ContractError::ContributionTooLow);
         }
         
-        if params.contribution_deadline >= params.voting_deadline {
+        if params.hard_cap > 0 && params.soft_cap > params.hard_cap {
+            return Err(ContractError::ContributionTooHigh);
+        }
+        
+        if params.contribution_deadline >= params.proposal_deadline
+            || params.proposal_deadline >= params.voting_deadline
+        {
             return Err(ContractError::PoolDeadlinePassed);
         }
         
-        if params.quorum_percentage > 100 {
+        if params.quorum_percentage > 100 || params.bond_return_percentage > 100 {
             return Err(ContractError::QuorumNotReached);
         }
         
+        if params.required_confirmations == 0 {
+            return Err(ContractError::NoRequiredConfirmations);
+        }
+        
+        let matched = params.match_budget > 0;
         self.params = Some(params);
-        self.state = PoolState::ContributionPhase;
+        if !matched {
+            set_phase(&mut self.state, PoolState::ContributionPhase);
+        }
         
         Ok(())
     }
     
+    /// Deposit the match budget from the UTXO of `sponsor` into the pool
+    ///
+    /// Like a proposal bond, the UTXO must hold at least `match_budget` and
+    /// the deposit fee comes out of the budget, so `match_budget` becomes what
+    /// was deposited net of it. Returns that amount. The deposit opens the
+    /// pool for contributions.
+    pub fn deposit_match_budget(&mut self, pool: &AccountInfo, sponsor: &AccountInfo) -> Result<u64, ContractError> {
+        let params = self.params.as_ref().ok_or(ContractError::PoolNotInitialized)?;
+        
+        // The budget is deposited once, when the pool is created
+        if self.sponsor.is_some() || self.state != PoolState::Uninitialized {
+            return Err(ContractError::PoolAlreadyInitialized);
+        }
+        let (budget, max_fee) = (params.match_budget, params.max_fee);
+        
+        let deposited = self.sign_deposit(pool, sponsor, budget, get_fee_rate()?, max_fee, DepositFee::FromDeposit)?;
+        
+        if let Some(params) = self.params.as_mut() {
+            params.match_budget = deposited;
+        }
+        self.sponsor = Some(*sponsor.key);
+        emit!(ContractEvent::MatchBudgetDeposited {
+            sponsor: *sponsor.key,
+            amount: deposited,
+        });
+        set_phase(&mut self.state, PoolState::ContributionPhase);
+        
+        Ok(deposited)
+    }
+    
     /// Contribute to the pool
-    pub fn contribute(&mut self, contributor: Pubkey, amount: u64) -> Result<(), ContractError> {
+    ///
+    /// The contribution is deposited from the UTXO of `contributor`, which
+    /// must hold the amount plus the network fee of the deposit; the rest goes
+    /// back to the contributor as change. Private pools also need a `proof`
+    /// that the contributor is on the allowlist.
+    pub fn contribute(
+        &mut self,
+        pool: &AccountInfo,
+        contributor: &AccountInfo,
+        record: &mut ContributorRecord,
+        amount: u64,
+        proof: &[[u8; 32]],
+    ) -> Result<(), ContractError> {
         let params = self.params.as_ref().ok_or(ContractError::PoolNotInitialized)?;
         
+        // A matched pool does not open until its budget is deposited
+        if self.state == PoolState::Uninitialized {
+            return Err(ContractError::PoolNotInitialized);
+        }
+        
         if self.state != PoolState::ContributionPhase {
             return Err(ContractError::PoolDeadlinePassed);
         }
         
-        let now = Utc::now().timestamp();
+        let now = get_unix_timestamp()?;
         if now > params.contribution_deadline {
-            self.state = PoolState::VotingPhase;
+            set_phase(&mut self.state, PoolState::ProposalPhase);
             return Err(ContractError::PoolDeadlinePassed);
         }
         
+        if let Some(root) = &params.allowlist_root {
+            if !verify_allowlist_proof(root, &record.contributor, proof) {
+                return Err(ContractError::NotAllowlisted);
+            }
+        }
+        
         if amount < params.min_contribution {
             return Err(ContractError::ContributionTooLow);
         }
@@ -383,229 +729,1135 @@ impl Contract {
             return Err(ContractError::ContributionTooHigh);
         }
         
+        if params.hard_cap > 0 && self.total_balance + amount > params.hard_cap {
+            return Err(ContractError::HardCapReached);
+        }
+        
         // Update or add contribution
-        let current_contribution = self.contributions.get(&contributor).unwrap_or(&0);
-        let new_total = current_contribution + amount;
+        let new_total = record.amount + amount;
         
         if new_total > params.max_contribution {
             return Err(ContractError::ContributionTooHigh);
         }
         
-        self.contributions.insert(contributor, new_total);
+        let max_fee = params.max_fee;
+        self.sign_deposit(pool, contributor, amount, get_fee_rate()?, max_fee, DepositFee::FromChange)?;
+        let params = self.params.as_ref().ok_or(ContractError::PoolNotInitialized)?;
+        
+        if record.amount == 0 {
+            self.total_contributors += 1;
+        }
+        record.amount = new_total;
         self.total_balance += amount;
+        emit!(ContractEvent::Contributed {
+            contributor: record.contributor,
+            amount,
+            total_balance: self.total_balance,
+        });
+        
+        // A full pool can move on without waiting for the deadline, and so can
+        // one whose remaining room no valid contribution fits in, as long as it
+        // has reached its soft cap; otherwise it stays open to fail at the deadline
+        let room = params.hard_cap.saturating_sub(self.total_balance);
+        if params.close_at_hard_cap
+            && params.hard_cap > 0
+            && (room == 0 || room < params.min_contribution)
+            && self.total_balance >= params.soft_cap
+        {
+            set_phase(&mut self.state, PoolState::ProposalPhase);
+            self.contributions_closed_at = Some(now);
+        }
+        
+        self.update_match(record);
         
         Ok(())
     }
     
-    /// Submit a proposal
+    // Bring a contributor's sponsor match in line with their contribution,
+    // drawing on the budget first come, first served
+    fn update_match(&mut self, record: &mut ContributorRecord) {
+        // Quadratic pools distribute the budget across proposals instead
+        let (budget, ratio, cap) = match &self.params {
+            Some(params) if params.distribution == Distribution::WinnerTakeAll => {
+                (params.match_budget, params.match_ratio, params.match_cap)
+            }
+            _ => return,
+        };
+        
+        // Nothing is matched before the sponsor deposits the budget
+        if self.sponsor.is_none() {
+            return;
+        }
+        
+        // Once the budget is back with the sponsor nothing is matched
+        if self.match_returned {
+            record.matched = 0;
+            return;
+        }
+        
+        let mut target = record.amount.saturating_mul(ratio as u64) / 100;
+        if cap > 0 {
+            target = target.min(cap);
+        }
+        
+        if target > record.matched {
+            let added = (target - record.matched).min(budget - self.total_matched);
+            record.matched += added;
+            self.total_matched += added;
+        } else {
+            let released = record.matched - target;
+            record.matched = target;
+            self.total_matched -= released;
+        }
+    }
+    
+    // Funds the payout distributes: contributions, slashed bonds and any match
+    // budget the sponsor deposited and has not taken back
+    fn payout_funds(&self) -> u64 {
+        let budget = match &self.params {
+            Some(params) if self.sponsor.is_some() && !self.match_returned => params.match_budget,
+            _ => 0,
+        };
+        self.total_balance + self.slashed_bonds + budget
+    }
+    
+    // Funds held in the pool UTXO: the payout funds until they are paid out,
+    // plus unsettled bonds
+    fn held_funds(&self) -> u64 {
+        let payout_funds = if self.transfer_executed { 0 } else { self.payout_funds() };
+        payout_funds + self.total_bonds
+    }
+    
+    /// Submit a proposal, returning it to be stored at its derived account
+    ///
+    /// If the pool charges a bond, it is deposited from the UTXO of
+    /// `proposer_account`, which must hold at least `proposal_bond`, by a
+    /// transaction merging it into the pool's funds and returning the rest to
+    /// the proposer. The deposit fee comes out of the bond.
     pub fn submit_proposal(
         &mut self,
-        proposer: Pubkey,
+        pool: &AccountInfo,
+        proposer_account: &AccountInfo,
+        proposer: &mut ContributorRecord,
         bitcoin_address: String,
-        description: String,
-    ) -> Result<u64, ContractError> {
+        metadata: ProposalMetadata,
+    ) -> Result<Proposal, ContractError> {
+        self.check_soft_cap()?;
         let params = self.params.as_ref().ok_or(ContractError::PoolNotInitialized)?;
         
-        if self.state != PoolState::VotingPhase {
-            let now = Utc::now().timestamp();
-            if now > params.contribution_deadline {
-                self.state = PoolState::VotingPhase;
+        if self.state != PoolState::ProposalPhase {
+            let now = get_unix_timestamp()?;
+            if self.state != PoolState::ContributionPhase {
+                return Err(ContractError::ProposalPeriodEnded);
+            } else if now > params.contribution_deadline {
+                set_phase(&mut self.state, PoolState::ProposalPhase);
+                self.contributions_closed_at = Some(params.contribution_deadline);
             } else {
                 return Err(ContractError::PoolDeadlinePassed);
             }
         }
         
-        let now = Utc::now().timestamp();
-        if now > params.voting_deadline {
-            self.state = PoolState::ExecutionPhase;
-            return Err(ContractError::VotingPeriodEnded);
+        let now = get_unix_timestamp()?;
+        if now > params.proposal_deadline {
+            set_phase(&mut self.state, PoolState::VotingPhase);
+            return Err(ContractError::ProposalPeriodEnded);
         }
         
         // Check if proposer has contributed enough
-        let contribution = self.contributions.get(&proposer).unwrap_or(&0);
-        if *contribution < params.proposal_threshold {
+        if proposer.amount < params.proposal_threshold {
             return Err(ContractError::InsufficientContributionForProposal);
         }
         
+        if params.max_proposals_per_proposer > 0
+            && proposer.proposals >= params.max_proposals_per_proposer
+        {
+            return Err(ContractError::ProposalLimitReached);
+        }
+        
+        // Keep the proposal account bounded
+        if bitcoin_address.len() > MAX_BITCOIN_ADDRESS_LEN {
+            return Err(ContractError::ProposalTooLarge);
+        }
+        metadata.validate()?;
+        
         // Validate Bitcoin address (simple validation)
         if !is_valid_bitcoin_address(&bitcoin_address) {
             return Err(ContractError::InvalidBitcoinAddress);
         }
         
-        // Create and store proposal
+        // The bond is deposited on top of the proposer's contribution
+        let bond = match params.proposal_bond {
+            0 => 0,
+            bond => self.sign_deposit(pool, proposer_account, bond, get_fee_rate()?, params.max_fee, DepositFee::FromDeposit)?,
+        };
+        
+        // Create proposal
         let proposal_id = self.next_proposal_id;
         self.next_proposal_id += 1;
+        proposer.bonded += bond;
+        proposer.proposals += 1;
+        self.total_bonds += bond;
         
         let proposal = Proposal {
             id: proposal_id,
-            proposer,
+            proposer: proposer.contributor,
             bitcoin_address,
-            description,
+            metadata,
             votes: 0,
+            bond,
+            withdrawn: false,
+            amendments: Vec::new(),
+            funding: 0,
+            sqrt_sum: 0,
+        };
+        emit!(ContractEvent::ProposalSubmitted {
+            proposal_id,
+            proposer: proposal.proposer,
+            bitcoin_address: proposal.bitcoin_address.clone(),
+            metadata: proposal.metadata.clone(),
+            bond: proposal.bond,
+        });
+        
+        Ok(proposal)
+    }
+    
+    /// Replace a proposal's payout address and metadata, keeping the
+    /// previous version in its amendment history
+    pub fn amend_proposal(
+        &self,
+        proposer: &Pubkey,
+        proposal: &mut Proposal,
+        bitcoin_address: String,
+        metadata: ProposalMetadata,
+    ) -> Result<(), ContractError> {
+        self.check_proposal_editable(proposer, proposal)?;
+        
+        if bitcoin_address.len() > MAX_BITCOIN_ADDRESS_LEN
+            || proposal.amendments.len() >= MAX_AMENDMENTS
+        {
+            return Err(ContractError::ProposalTooLarge);
+        }
+        metadata.validate()?;
+        
+        if !is_valid_bitcoin_address(&bitcoin_address) {
+            return Err(ContractError::InvalidBitcoinAddress);
+        }
+        
+        let previous = ProposalAmendment {
+            bitcoin_address: std::mem::replace(&mut proposal.bitcoin_address, bitcoin_address),
+            metadata: std::mem::replace(&mut proposal.metadata, metadata),
+            amended_at: get_unix_timestamp()?,
+        };
+        proposal.amendments.push(previous);
+        emit!(ContractEvent::ProposalAmended {
+            proposal_id: proposal.id,
+            bitcoin_address: proposal.bitcoin_address.clone(),
+            metadata: proposal.metadata.clone(),
+        });
+        
+        Ok(())
+    }
+    
+    /// Withdraw a proposal, refunding its bond to the proposer
+    ///
+    /// Returns the bond refund, or None if the proposal had no bond.
+    pub fn withdraw_proposal(
+        &mut self,
+        pool: &AccountInfo,
+        proposer: &mut ContributorRecord,
+        proposal: &mut Proposal,
+    ) -> Result<Option<Refund>, ContractError> {
+        self.check_proposal_editable(&proposer.contributor, proposal)?;
+        let params = self.params.as_ref().ok_or(ContractError::PoolNotInitialized)?;
+        
+        let bond = proposal.bond;
+        let refund = match bond {
+            0 => None,
+            _ => Some(self.sign_refund(pool, &proposer.contributor, bond, get_fee_rate()?, params.max_fee)?),
         };
         
-        self.proposals.insert(proposal_id, proposal);
+        proposer.bonded -= bond;
+        self.total_bonds -= bond;
+        proposal.bond = 0;
+        proposal.withdrawn = true;
+        emit!(ContractEvent::ProposalWithdrawn { proposal_id: proposal.id });
         
-        Ok(proposal_id)
+        Ok(refund)
+    }
+    
+    // Proposals can only be changed by their proposer, before voting starts
+    fn check_proposal_editable(&self, proposer: &Pubkey, proposal: &Proposal) -> Result<(), ContractError> {
+        let params = self.params.as_ref().ok_or(ContractError::PoolNotInitialized)?;
+        
+        if self.state != PoolState::ProposalPhase {
+            return Err(ContractError::ProposalPeriodEnded);
+        }
+        
+        let now = get_unix_timestamp()?;
+        if now > params.proposal_deadline {
+            return Err(ContractError::ProposalPeriodEnded);
+        }
+        
+        if *proposer != proposal.proposer {
+            return Err(ContractError::NotProposer);
+        }
+        
+        if proposal.withdrawn {
+            return Err(ContractError::ProposalWithdrawn);
+        }
+        
+        if proposal.votes > 0 {
+            return Err(ContractError::ProposalHasVotes);
+        }
+        
+        Ok(())
     }
     
     /// Cast a vote for a proposal
-    pub fn cast_vote(&mut self, voter: Pubkey, proposal_id: u64) -> Result<(), ContractError> {
+    pub fn cast_vote(&mut self, voter: &mut ContributorRecord, proposal: &mut Proposal) -> Result<(), ContractError> {
+        self.check_voting_open()?;
         let params = self.params.as_ref().ok_or(ContractError::PoolNotInitialized)?;
         
-        if self.state != PoolState::VotingPhase {
-            let now = Utc::now().timestamp();
-            if now > params.contribution_deadline && now <= params.voting_deadline {
-                self.state = PoolState::VotingPhase;
-            } else if now > params.voting_deadline {
-                self.state = PoolState::ExecutionPhase;
-                return Err(ContractError::VotingPeriodEnded);
-            } else {
-                return Err(ContractError::PoolDeadlinePassed);
-            }
+        if params.distribution != Distribution::WinnerTakeAll {
+            return Err(ContractError::WrongDistribution);
         }
         
         // Check if voter has contributed enough
-        let contribution = self.contributions.get(&voter).unwrap_or(&0);
-        if *contribution < params.voting_threshold {
+        if voter.amount < params.voting_threshold {
             return Err(ContractError::InsufficientContributionForVoting);
         }
         
         // Check if proposal exists
-        if !self.proposals.contains_key(&proposal_id) {
+        if proposal.id == 0 || proposal.id >= self.next_proposal_id {
             return Err(ContractError::ProposalNotFound);
         }
         
+        if proposal.withdrawn {
+            return Err(ContractError::ProposalWithdrawn);
+        }
+        
         // Check if already voted
-        if self.votes.contains_key(&voter) {
+        if voter.vote.is_some() {
             return Err(ContractError::AlreadyVoted);
         }
         
         // Record vote
-        self.votes.insert(voter, proposal_id);
-        
-        // Update proposal vote count
-        if let Some(proposal) = self.proposals.get_mut(&proposal_id) {
-            proposal.votes += 1;
+        voter.vote = Some(proposal.id);
+        proposal.votes += 1;
+        self.total_votes += 1;
+        
+        // The first proposal to reach a vote count keeps the lead on ties
+        if proposal.votes > self.leading_votes {
+            self.leading_proposal = Some(proposal.id);
+            self.leading_votes = proposal.votes;
         }
+        emit!(ContractEvent::VoteCast {
+            voter: voter.contributor,
+            proposal_id: proposal.id,
+        });
         
         Ok(())
     }
     
-    /// Execute transfer to the winning proposal
-    pub fn execute_transfer(&mut self, program_id: &Pubkey, accounts: &[AccountInfo]) -> Result<(), ContractError> {
+    /// Direct part of a contribution to a proposal in a quadratic pool
+    ///
+    /// A contributor can fund several proposals, and fund the same one more
+    /// than once, up to their contribution.
+    pub fn fund_proposal(
+        &mut self,
+        funder: &mut ContributorRecord,
+        proposal: &mut Proposal,
+        amount: u64,
+    ) -> Result<(), ContractError> {
+        self.check_voting_open()?;
         let params = self.params.as_ref().ok_or(ContractError::PoolNotInitialized)?;
         
-        if self.state != PoolState::ExecutionPhase {
-            let now = Utc::now().timestamp();
-            if now <= params.voting_deadline {
-                return Err(ContractError::VotingPeriodNotEnded);
-            } else {
-                self.state = PoolState::ExecutionPhase;
-            }
+        if params.distribution != Distribution::Quadratic {
+            return Err(ContractError::WrongDistribution);
         }
         
-        if self.transfer_executed {
-            return Err(ContractError::TransferAlreadyExecuted);
+        if funder.amount < params.voting_threshold {
+            return Err(ContractError::InsufficientContributionForVoting);
         }
         
-        if self.proposals.is_empty() {
-            return Err(ContractError::NoProposalsSubmitted);
+        if proposal.id == 0 || proposal.id >= self.next_proposal_id {
+            return Err(ContractError::ProposalNotFound);
         }
         
-        if self.votes.is_empty() {
-            return Err(ContractError::NoVotesCast);
+        if proposal.withdrawn {
+            return Err(ContractError::ProposalWithdrawn);
         }
         
-        // Check quorum
-        let total_contributors = self.contributions.len() as f64;
-        let total_voters = self.votes.len() as f64;
-        let quorum_percentage = params.quorum_percentage as f64 / 100.0;
-        
-        if (total_voters / total_contributors) < quorum_percentage {
-            return Err(ContractError::QuorumNotReached);
+        let available = funder.amount - funder.allocated();
+        if amount == 0 || amount > available {
+            return Err(ContractError::AllocationTooHigh);
         }
         
-        // Find winning proposal
-        let mut winning_proposal_id = 0;
-        let mut max_votes = 0;
-        
-        for (id, proposal) in &self.proposals {
-            if proposal.votes > max_votes {
-                max_votes = proposal.votes;
-                winning_proposal_id = *id;
+        let index = match funder.allocations.iter().position(|allocation| allocation.proposal_id == proposal.id) {
+            Some(index) => index,
+            None => {
+                if funder.allocations.len() >= MAX_ALLOCATIONS {
+                    return Err(ContractError::AllocationLimitReached);
+                }
+                funder.allocations.push(Allocation { proposal_id: proposal.id, amount: 0 });
+                proposal.votes += 1;
+                funder.allocations.len() - 1
             }
-        }
+        };
         
-        if winning_proposal_id == 0 {
-            return Err(ContractError::NoVotesCast);
+        // Replace the funder's old square root with the new one
+        let allocation = &mut funder.allocations[index];
+        proposal.sqrt_sum -= allocation.amount.isqrt();
+        allocation.amount += amount;
+        proposal.sqrt_sum += allocation.amount.isqrt();
+        proposal.funding += amount;
+        
+        // Funders count towards quorum once, like voters
+        if funder.vote.is_none() {
+            funder.vote = Some(proposal.id);
+            self.total_votes += 1;
         }
+        emit!(ContractEvent::ProposalFunded {
+            funder: funder.contributor,
+            proposal_id: proposal.id,
+            amount,
+        });
         
-        // Get winning proposal
-        let winning_proposal = self.proposals.get(&winning_proposal_id)
-            .ok_or(ContractError::ProposalNotFound)?;
-        
-        // Create Bitcoin transaction
-        let account_info_iter = &mut accounts.iter();
-        let payer = next_account_info(account_info_iter)?;
+        Ok(())
+    }
+    
+    // Move to the voting phase once proposals close, failing if voting is over
+    fn check_voting_open(&mut self) -> Result<(), ContractError> {
+        self.check_soft_cap()?;
+        let params = self.params.as_ref().ok_or(ContractError::PoolNotInitialized)?;
         
-        // Get Bitcoin script pubkey from address
-        let script_pubkey = get_account_script_pubkey(winning_proposal.bitcoin_address.as_str())?;
+        if self.state != PoolState::VotingPhase {
+            let now = get_unix_timestamp()?;
+            if self.state == PoolState::ContributionPhase && now > params.proposal_deadline {
+                // Nothing ran since contributions closed
+                self.contributions_closed_at = Some(params.contribution_deadline);
+            }
+            if now > params.proposal_deadline && now <= params.voting_deadline {
+                set_phase(&mut self.state, PoolState::VotingPhase);
+            } else if now > params.voting_deadline {
+                set_phase(&mut self.state, PoolState::ExecutionPhase);
+                return Err(ContractError::VotingPeriodEnded);
+            } else {
+                return Err(ContractError::VotingNotStarted);
+            }
+        }
         
-        // Create transaction
+        Ok(())
+    }
+    
+    /// Settle a proposal's bond once voting has closed, before the transfer
+    ///
+    /// The bond is refunded to the proposer if the proposal received at least
+    /// `bond_return_percentage` of all votes, otherwise it is slashed into the
+    /// pool and paid out with the contributions. Returns the refund, or None if
+    /// the bond was slashed.
+    pub fn settle_proposal_bond(
+        &mut self,
+        pool: &AccountInfo,
+        proposer: &mut ContributorRecord,
+        proposal: &mut Proposal,
+    ) -> Result<Option<Refund>, ContractError> {
+        let params = self.params.as_ref().ok_or(ContractError::PoolNotInitialized)?;
+        
+        if matches!(
+            self.state,
+            PoolState::ContributionPhase | PoolState::ProposalPhase | PoolState::VotingPhase
+        ) {
+            let now = get_unix_timestamp()?;
+            if now <= params.voting_deadline {
+                return Err(ContractError::VotingPeriodNotEnded);
+            }
+        }
+        
+        if proposer.contributor != proposal.proposer {
+            return Err(ContractError::ContributorNotFound);
+        }
+        
+        if proposal.bond == 0 {
+            return Err(ContractError::BondAlreadySettled);
+        }
+        let bond = proposal.bond;
+        
+        let returned = proposal.votes * 100 >= self.total_votes * params.bond_return_percentage as u64
+            && (proposal.votes > 0 || params.bond_return_percentage == 0);
+        
+        // A slashed bond stays in the pool and joins the payout
+        let refund = match returned {
+            true => Some(self.sign_refund(pool, &proposer.contributor, bond, get_fee_rate()?, params.max_fee)?),
+            false => None,
+        };
+        
+        proposer.bonded -= bond;
+        self.total_bonds -= bond;
+        if !returned {
+            self.slashed_bonds += bond;
+        }
+        proposal.bond = 0;
+        emit!(ContractEvent::BondSettled {
+            proposal_id: proposal.id,
+            returned,
+        });
+        
+        Ok(refund)
+    }
+    
+    /// Execute transfer to the winning proposal
+    ///
+    /// The network fee is `fee_rate` (sat/vB) times the estimated payout size and
+    /// is deducted from the payout. Without an explicit rate the runtime estimate
+    /// is used. Since the fee comes out of the payout, only the pool creator or
+    /// the winning proposer, as `authority`, can choose the rate. Either way it
+    /// must be at least `MIN_FEE_RATE`.
+    ///
+    /// Winner-take-all pools need the winning proposal in `proposals`; quadratic
+    /// pools need every proposal, in id order.
+    pub fn execute_transfer(
+        &mut self,
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        authority: Option<&Pubkey>,
+        proposals: &[Proposal],
+        fee_rate: Option<u64>,
+    ) -> Result<(), ContractError> {
+        self.check_soft_cap()?;
+        let params = self.params.as_ref().ok_or(ContractError::PoolNotInitialized)?;
+        
+        if self.state != PoolState::ExecutionPhase {
+            let now = get_unix_timestamp()?;
+            if now <= params.voting_deadline {
+                return Err(ContractError::VotingPeriodNotEnded);
+            }
+            if self.state == PoolState::ContributionPhase {
+                self.contributions_closed_at = Some(params.contribution_deadline);
+            }
+            set_phase(&mut self.state, PoolState::ExecutionPhase);
+        }
+        
+        if self.transfer_executed {
+            return Err(ContractError::TransferAlreadyExecuted);
+        }
+        
+        if self.next_proposal_id == 1 {
+            return Err(ContractError::NoProposalsSubmitted);
+        }
+        
+        if self.total_votes == 0 {
+            return Err(ContractError::NoVotesCast);
+        }
+        
+        // Check quorum
+        let total_contributors = self.total_contributors as f64;
+        let total_voters = self.total_votes as f64;
+        let quorum_percentage = params.quorum_percentage as f64 / 100.0;
+        
+        if (total_voters / total_contributors) < quorum_percentage {
+            return Err(ContractError::QuorumNotReached);
+        }
+        
+        // Every bond is returned or slashed into the payout first
+        if self.total_bonds > 0 {
+            return Err(ContractError::BondsNotSettled);
+        }
+        
+        // The winner is the proposal leading when voting closed
+        let winning_proposal_id = match params.distribution {
+            Distribution::WinnerTakeAll => Some(self.leading_proposal.ok_or(ContractError::NoVotesCast)?),
+            Distribution::Quadratic => None,
+        };
+        let recipients = self.payout_recipients(proposals, winning_proposal_id)?;
+        
+        // Create Bitcoin transaction
+        let account_info_iter = &mut accounts.iter();
+        let payer = next_account_info(account_info_iter)?;
+        
+        let fee_rate = match (fee_rate, authority) {
+            (Some(rate), Some(authority)) => {
+                self.check_payout_authority(authority, proposals, winning_proposal_id)?;
+                rate
+            }
+            (Some(_), None) => return Err(ContractError::NotPayoutAuthority),
+            (None, _) => get_fee_rate()?,
+        };
+        if fee_rate < MIN_FEE_RATE {
+            return Err(ContractError::FeeRateTooLow);
+        }
+        let payout = self.sign_payout(payer, recipients, fee_rate, params.max_fee)?;
+        
+        // Mark as executed; the pool completes once the payout confirms
+        self.winning_proposal = winning_proposal_id;
+        self.transfer_executed = true;
+        set_phase(&mut self.state, PoolState::AwaitingConfirmation);
+        emit!(ContractEvent::TransferExecuted {
+            txid: payout.txid,
+            fee_rate: payout.fee_rate,
+            fee: payout.fee,
+            winning_proposal: self.winning_proposal,
+        });
+        self.pending_payout = Some(payout);
+        
+        // Add state transition
+        add_state_transition(payer, program_id, self)?;
+        
+        Ok(())
+    }
+    
+    /// Re-issue the pending payout with a higher fee rate, replacing it via RBF
+    ///
+    /// Only the pool creator or the winning proposer can bump the fee, since
+    /// the higher fee comes out of the payout.
+    pub fn bump_payout_fee(
+        &mut self,
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        authority: &Pubkey,
+        proposals: &[Proposal],
+        new_fee_rate: u64,
+    ) -> Result<(), ContractError> {
+        let params = self.params.as_ref().ok_or(ContractError::PoolNotInitialized)?;
+        
+        let pending = self.pending_payout.as_ref().ok_or(ContractError::NoPendingPayout)?;
+        self.check_payout_authority(authority, proposals, self.winning_proposal)?;
+        
+        // BIP 125 requires the replacement to pay a strictly higher fee rate
+        if new_fee_rate <= pending.fee_rate {
+            return Err(ContractError::FeeRateNotIncreased);
+        }
+        
+        let recipients = self.payout_recipients(proposals, self.winning_proposal)?;
+        
+        let account_info_iter = &mut accounts.iter();
+        let payer = next_account_info(account_info_iter)?;
+        
+        let mut payout = self.sign_payout(payer, recipients, new_fee_rate, params.max_fee)?;
+        
+        // The replaced transaction can still be mined first, so keep its txid
+        payout.replaced = pending.replaced.clone();
+        payout.replaced.push(pending.txid);
+        emit!(ContractEvent::PayoutFeeBumped {
+            txid: payout.txid,
+            replaced: pending.txid,
+            fee_rate: payout.fee_rate,
+            fee: payout.fee,
+        });
+        self.pending_payout = Some(payout);
+        
+        // Add state transition
+        add_state_transition(payer, program_id, self)?;
+        
+        Ok(())
+    }
+    
+    // Only the pool creator and the winning proposer, found in `proposals`,
+    // can choose the payout's fee rate
+    fn check_payout_authority(
+        &self,
+        authority: &Pubkey,
+        proposals: &[Proposal],
+        winner: Option<u64>,
+    ) -> Result<(), ContractError> {
+        let winner = winner.and_then(|id| proposals.iter().find(|proposal| proposal.id == id));
+        if *authority != self.pool.creator && winner.map(|proposal| proposal.proposer) != Some(*authority) {
+            return Err(ContractError::NotPayoutAuthority);
+        }
+        Ok(())
+    }
+    
+    /// Complete the pool once any version of the pending payout has enough
+    /// confirmations
+    pub fn confirm_payout(&mut self) -> Result<u32, ContractError> {
+        let params = self.params.as_ref().ok_or(ContractError::PoolNotInitialized)?;
+        
+        let pending = match (&self.state, &self.pending_payout) {
+            (PoolState::AwaitingConfirmation, Some(pending)) => pending,
+            _ => return Err(ContractError::NoPendingPayout),
+        };
+        
+        // Only one version can be mined, since they all spend the same inputs
+        let (mut confirmed, mut confirmations) = (pending.txid, 0);
+        for txid in pending.replaced.iter().chain([&pending.txid]) {
+            let depth = get_transaction_confirmations(&Txid(*txid))?;
+            if depth > confirmations {
+                (confirmed, confirmations) = (*txid, depth);
+            }
+        }
+        if confirmations < params.required_confirmations {
+            return Err(ContractError::PayoutNotConfirmed);
+        }
+        
+        emit!(ContractEvent::PayoutConfirmed { txid: confirmed, confirmations });
+        self.pending_payout = None;
+        set_phase(&mut self.state, PoolState::Completed);
+        
+        Ok(confirmations)
+    }
+    
+    /// Amount each proposal receives from a quadratic pool, before the
+    /// network fee
+    ///
+    /// Each proposal gets what was directed to it plus a share of the matching
+    /// pool (the sponsor's budget, slashed bonds and any undirected
+    /// contributions) weighted by the square of the sum of square roots of its
+    /// contributions. Needs every proposal, in id order.
+    pub fn quadratic_payouts(&self, proposals: &[Proposal]) -> Result<Vec<u64>, ContractError> {
+        if proposals.len() as u64 != self.next_proposal_id - 1
+            || proposals.iter().zip(1..).any(|(proposal, id)| proposal.id != id)
+        {
+            return Err(ContractError::ProposalNotFound);
+        }
+        
+        // Undirected contributions and slashed bonds join the sponsor's budget
+        // as the matching pool
+        let directed: u64 = proposals.iter().map(|proposal| proposal.funding).sum();
+        let matching_pool = (self.payout_funds() - directed) as u128;
+        
+        let weights: Vec<u128> = proposals
+            .iter()
+            .map(|proposal| (proposal.sqrt_sum as u128).pow(2))
+            .collect();
+        let total_weight: u128 = weights.iter().sum();
+        if total_weight == 0 {
+            return Err(ContractError::NoVotesCast);
+        }
+        
+        let mut amounts: Vec<u64> = proposals
+            .iter()
+            .zip(&weights)
+            .map(|(proposal, weight)| proposal.funding + (matching_pool * weight / total_weight) as u64)
+            .collect();
+        
+        // Rounding dust goes to the proposal with the most weight
+        let paid: u64 = amounts.iter().sum();
+        let top = (0..weights.len()).max_by_key(|&i| (weights[i], std::cmp::Reverse(i))).unwrap_or(0);
+        amounts[top] += self.payout_funds() - paid;
+        
+        Ok(amounts)
+    }
+    
+    /// Script pubkeys and amounts the payout goes to, before the network fee
+    ///
+    /// A winner-take-all payout goes to `winner`, found in `proposals`. A
+    /// quadratic payout needs every proposal, in id order.
+    fn payout_recipients(
+        &self,
+        proposals: &[Proposal],
+        winner: Option<u64>,
+    ) -> Result<Vec<(Vec<u8>, u64)>, ContractError> {
+        let params = self.params.as_ref().ok_or(ContractError::PoolNotInitialized)?;
+        
+        if params.distribution == Distribution::WinnerTakeAll {
+            // The winner gets the contributions, slashed bonds and the sponsor match
+            let winner = winner
+                .and_then(|id| proposals.iter().find(|proposal| proposal.id == id))
+                .ok_or(ContractError::ProposalNotFound)?;
+            let script_pubkey = get_account_script_pubkey(&winner.bitcoin_address)?;
+            return Ok(vec![(script_pubkey, self.total_balance + self.slashed_bonds + self.total_matched)]);
+        }
+        
+        let amounts = self.quadratic_payouts(proposals)?;
+        
+        let mut recipients = Vec::new();
+        for (proposal, amount) in proposals.iter().zip(amounts) {
+            if amount > 0 {
+                recipients.push((get_account_script_pubkey(&proposal.bitcoin_address)?, amount));
+            }
+        }
+        Ok(recipients)
+    }
+    
+    /// Build the payout to `recipients`, sharing the network fee in proportion
+    /// to what each receives, and hand it to the runtime for signing
+    fn sign_payout(
+        &self,
+        payer: &AccountInfo,
+        recipients: Vec<(Vec<u8>, u64)>,
+        fee_rate: u64,
+        max_fee: u64,
+    ) -> Result<PendingPayout, ContractError> {
+        // Create transaction
         let block_height = get_bitcoin_block_height()?;
         let lock_time = LockTime::from_height(block_height)?;
         
-        // Prepare transaction to sign
-        let transaction = Transaction {
+        let payout: u64 = recipients.iter().map(|(_, value)| value).sum();
+        let mut output: Vec<TxOut> = recipients
+            .into_iter()
+            .map(|(script_pubkey, value)| TxOut { value, script_pubkey })
+            .collect();
+        let recipient_count = output.len();
+        
+        // Whatever the recipients don't get is unused match budget, which goes
+        // back to the sponsor
+        let unused_match = self.payout_funds().checked_sub(payout).ok_or(ContractError::InsufficientDeposit)?;
+        if let (Some(sponsor), true) = (&self.sponsor, unused_match > 0) {
+            output.push(TxOut {
+                value: unused_match,
+                script_pubkey: taproot_script_pubkey(sponsor),
+            });
+        }
+        
+        // Prepare transaction to sign, spending the pool UTXO to the winner.
+        // Signal RBF so a stuck payout can be replaced with a higher fee.
+        let mut transaction = Transaction {
             version: Version::TWO,
             lock_time,
-            // Other transaction details would be filled here
-            // This is simplified for the example
+            input: self.pool_inputs(payer),
+            output,
         };
         
+        // Deduct the network fee from the payout
+        let fee = estimate_vsize(&transaction).saturating_mul(fee_rate);
+        if (max_fee > 0 && fee > max_fee) || fee >= payout {
+            return Err(ContractError::FeeTooHigh);
+        }
+        let net = payout - fee;
+        let mut paid = 0;
+        for output in &mut transaction.output[..recipient_count] {
+            output.value = (output.value as u128 * net as u128 / payout as u128) as u64;
+            paid += output.value;
+        }
+        transaction.output[0].value += net - paid;
+        let txid = transaction.compute_txid();
+        let inputs_to_sign = pool_signatures(&transaction, payer.key);
+        
         // Set transaction to sign
         let transaction_to_sign = TransactionToSign {
             transaction,
-            inputs_to_sign: vec![
-                InputToSign {
-                    // Input details would be filled here
-                    // This is simplified for the example
-                }
-            ],
+            inputs_to_sign,
         };
         
         set_transaction_to_sign(transaction_to_sign)?;
         
-        // Mark as executed
-        self.winning_proposal = Some(winning_proposal_id);
-        self.transfer_executed = true;
-        self.state = PoolState::Completed;
+        Ok(PendingPayout {
+            txid: txid.0,
+            fee_rate,
+            fee,
+            replaced: Vec::new(),
+        })
+    }
+    
+    /// Withdraw part of a contribution during the contribution phase
+    ///
+    /// The remaining contribution must be zero or at least `min_contribution`.
+    /// The refund spends the pool UTXO, paying `amount` less the network fee to
+    /// the contributor and the rest of the balance back to the pool.
+    pub fn withdraw(
+        &mut self,
+        pool: &AccountInfo,
+        record: &mut ContributorRecord,
+        amount: u64,
+    ) -> Result<Refund, ContractError> {
+        let params = self.params.as_ref().ok_or(ContractError::PoolNotInitialized)?;
         
-        // Add state transition
-        add_state_transition(payer, program_id, self)?;
+        if self.state != PoolState::ContributionPhase {
+            return Err(ContractError::PoolDeadlinePassed);
+        }
+        
+        let now = get_unix_timestamp()?;
+        if now > params.contribution_deadline {
+            return Err(ContractError::PoolDeadlinePassed);
+        }
+        
+        if record.amount == 0 {
+            return Err(ContractError::ContributorNotFound);
+        }
+        
+        if amount == 0 || amount > record.amount {
+            return Err(ContractError::InvalidWithdrawalAmount);
+        }
+        
+        let remaining = record.amount - amount;
+        if remaining != 0 && remaining < params.min_contribution {
+            return Err(ContractError::InvalidWithdrawalAmount);
+        }
+        
+        let refund = self.sign_refund(pool, &record.contributor, amount, get_fee_rate()?, params.max_fee)?;
+        
+        record.amount = remaining;
+        if remaining == 0 {
+            self.total_contributors -= 1;
+        }
+        self.total_balance -= amount;
+        self.update_match(record);
+        emit!(ContractEvent::Withdrawn {
+            contributor: record.contributor,
+            amount,
+            remaining,
+        });
+        
+        Ok(refund)
+    }
+    
+    // Outpoints currently holding the pool's funds: the change of the last
+    // refund, and the account's UTXO unless a refund already spent it
+    fn pool_outpoints(&self, pool: &AccountInfo) -> Vec<OutPoint> {
+        let account = pool.utxo.to_outpoint();
+        let spent = self.spent_account_utxo.map(|utxo| OutPoint { txid: Txid(utxo.txid), vout: utxo.vout });
+        self.pool_utxo
+            .map(|utxo| OutPoint { txid: Txid(utxo.txid), vout: utxo.vout })
+            .into_iter()
+            .chain((spent != Some(account)).then_some(account))
+            .collect()
+    }
+    
+    // Spend every pool outpoint, signalling RBF
+    fn pool_inputs(&self, pool: &AccountInfo) -> Vec<TxIn> {
+        self.pool_outpoints(pool)
+            .into_iter()
+            .map(|previous_output| TxIn {
+                previous_output,
+                script_sig: Vec::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Vec::new(),
+            })
+            .collect()
+    }
+    
+    /// Build a refund of `amount` to `recipient`, net of the network fee, with
+    /// the rest of the pool funds as change, and hand it to the runtime
+    ///
+    /// The change goes to the network key, so the pool can spend it, and
+    /// later refunds and the payout spend it along with anything deposited to
+    /// the account since.
+    fn sign_refund(
+        &mut self,
+        pool: &AccountInfo,
+        recipient: &Pubkey,
+        amount: u64,
+        fee_rate: u64,
+        max_fee: u64,
+    ) -> Result<Refund, ContractError> {
+        let block_height = get_bitcoin_block_height()?;
+        let lock_time = LockTime::from_height(block_height)?;
+        
+        let mut output = vec![TxOut {
+            value: amount,
+            script_pubkey: taproot_script_pubkey(recipient),
+        }];
+        let change = self.held_funds() - amount;
+        if change > 0 {
+            let network_key = Pubkey::from_bytes(get_network_xonly_pubkey()?);
+            output.push(TxOut {
+                value: change,
+                script_pubkey: taproot_script_pubkey(&network_key),
+            });
+        }
+        let mut transaction = Transaction {
+            version: Version::TWO,
+            lock_time,
+            input: self.pool_inputs(pool),
+            output,
+        };
+        
+        // The recipient pays the network fee out of the refund
+        let fee = estimate_vsize(&transaction).saturating_mul(fee_rate);
+        if (max_fee > 0 && fee > max_fee) || fee >= amount {
+            return Err(ContractError::FeeTooHigh);
+        }
+        transaction.output[0].value = amount - fee;
+        let txid = transaction.compute_txid();
+        let inputs_to_sign = pool_signatures(&transaction, pool.key);
+        
+        set_transaction_to_sign(TransactionToSign {
+            transaction,
+            inputs_to_sign,
+        })?;
+        self.move_pool_funds(pool, (change > 0).then_some(PoolUtxo { txid: txid.0, vout: 1 }));
+        
+        Ok(Refund {
+            txid: txid.0,
+            value: amount - fee,
+            fee,
+        })
+    }
+    
+    /// Build a deposit of `amount` from `depositor`'s UTXO into the pool,
+    /// merging it with the pool funds, and hand it to the runtime
+    ///
+    /// The UTXO must hold at least `amount`; anything above it goes back to
+    /// the depositor as change. The depositor pays the network fee out of the
+    /// deposit or, with `DepositFee::FromChange`, out of the change, in which
+    /// case the UTXO must also cover the fee. Returns the amount deposited,
+    /// net of any fee.
+    fn sign_deposit(
+        &mut self,
+        pool: &AccountInfo,
+        depositor: &AccountInfo,
+        amount: u64,
+        fee_rate: u64,
+        max_fee: u64,
+        paid_from: DepositFee,
+    ) -> Result<u64, ContractError> {
+        let block_height = get_bitcoin_block_height()?;
+        let lock_time = LockTime::from_height(block_height)?;
+        
+        let deposit = depositor.utxo.to_outpoint();
+        let held = get_bitcoin_tx_output_value(&deposit.txid, deposit.vout)?.unwrap_or(0);
+        if held < amount {
+            return Err(ContractError::InsufficientDeposit);
+        }
+        
+        let network_key = Pubkey::from_bytes(get_network_xonly_pubkey()?);
+        let mut input = self.pool_inputs(pool);
+        input.push(TxIn {
+            previous_output: deposit,
+            script_sig: Vec::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Vec::new(),
+        });
+        let mut output = vec![TxOut {
+            value: self.held_funds() + amount,
+            script_pubkey: taproot_script_pubkey(&network_key),
+        }];
+        if held > amount {
+            output.push(TxOut {
+                value: held - amount,
+                script_pubkey: taproot_script_pubkey(depositor.key),
+            });
+        }
+        let mut transaction = Transaction {
+            version: Version::TWO,
+            lock_time,
+            input,
+            output,
+        };
+        
+        let fee = estimate_vsize(&transaction).saturating_mul(fee_rate);
+        if max_fee > 0 && fee > max_fee {
+            return Err(ContractError::FeeTooHigh);
+        }
+        let deposited = match paid_from {
+            DepositFee::FromDeposit if fee >= amount => return Err(ContractError::FeeTooHigh),
+            DepositFee::FromDeposit => {
+                transaction.output[0].value -= fee;
+                amount - fee
+            }
+            DepositFee::FromChange if held - amount < fee => return Err(ContractError::InsufficientDeposit),
+            DepositFee::FromChange => {
+                // Change that only covers the fee is dropped
+                match held - amount - fee {
+                    0 => transaction.output.truncate(1),
+                    change => transaction.output[1].value = change,
+                }
+                amount
+            }
+        };
+        let txid = transaction.compute_txid();
+        
+        // The pool signs its own inputs and the depositor the last one
+        let mut inputs_to_sign = pool_signatures(&transaction, pool.key);
+        if let Some(last) = inputs_to_sign.last_mut() {
+            last.signer = *depositor.key;
+        }
+        
+        set_transaction_to_sign(TransactionToSign {
+            transaction,
+            inputs_to_sign,
+        })?;
+        self.move_pool_funds(pool, Some(PoolUtxo { txid: txid.0, vout: 0 }));
+        
+        Ok(deposited)
+    }
+    
+    // Record that a transaction spent the pool's outpoints, leaving its funds
+    // in `change`
+    fn move_pool_funds(&mut self, pool: &AccountInfo, change: Option<PoolUtxo>) {
+        let account = pool.utxo.to_outpoint();
+        self.spent_account_utxo = Some(PoolUtxo { txid: account.txid.0, vout: account.vout });
+        self.pool_utxo = change;
+    }
+    
+    // Fail the pool if contributions closed below the soft cap
+    fn check_soft_cap(&mut self) -> Result<(), ContractError> {
+        let params = self.params.as_ref().ok_or(ContractError::PoolNotInitialized)?;
+        
+        if self.state == PoolState::ContributionPhase
+            && get_unix_timestamp()? > params.contribution_deadline
+            && self.total_balance < params.soft_cap
+        {
+            set_phase(&mut self.state, PoolState::Failed);
+        }
+        
+        if self.state == PoolState::Failed {
+            return Err(ContractError::PoolFailed);
+        }
         
         Ok(())
     }
     
-    /// Emergency withdraw before voting deadline
-    pub fn emergency_withdraw(&mut self, contributor: Pubkey) -> Result<u64, ContractError> {
-        let _params = self.params.as_ref().ok_or(ContractError::PoolNotInitialized)?;
+    /// Refund a contributor's whole contribution from a pool that failed to
+    /// reach its soft cap
+    pub fn claim_refund(
+        &mut self,
+        pool: &AccountInfo,
+        record: &mut ContributorRecord,
+    ) -> Result<Refund, ContractError> {
+        if self.check_soft_cap().is_ok() {
+            return Err(ContractError::PoolNotFailed);
+        }
+        let params = self.params.as_ref().ok_or(ContractError::PoolNotInitialized)?;
         
-        // Only allow withdrawals before voting begins
-        if self.state != PoolState::ContributionPhase {
-            return Err(ContractError::PoolDeadlinePassed);
+        if record.amount == 0 {
+            return Err(ContractError::ContributorNotFound);
         }
+        let amount = record.amount;
         
-        // Get contribution
-        let contribution = self.contributions.get(&contributor).ok_or(ContractError::ContributorNotFound)?;
-        let amount = *contribution;
+        let refund = self.sign_refund(pool, &record.contributor, amount, get_fee_rate()?, params.max_fee)?;
         
-        // Remove contribution
-        self.contributions.remove(&contributor);
+        record.amount = 0;
+        self.total_contributors -= 1;
         self.total_balance -= amount;
+        self.update_match(record);
+        emit!(ContractEvent::Refunded {
+            recipient: record.contributor,
+            amount,
+            txid: refund.txid,
+        });
+        
+        Ok(refund)
+    }
+    
+    /// Return the whole match budget to the sponsor of a pool that failed to
+    /// reach its soft cap
+    pub fn reclaim_match_budget(&mut self, pool: &AccountInfo, sponsor: &Pubkey) -> Result<Refund, ContractError> {
+        if self.check_soft_cap().is_ok() {
+            return Err(ContractError::PoolNotFailed);
+        }
+        let params = self.params.as_ref().ok_or(ContractError::PoolNotInitialized)?;
+        
+        if self.sponsor != Some(*sponsor) {
+            return Err(ContractError::NotSponsor);
+        }
+        
+        if self.match_returned || params.match_budget == 0 {
+            return Err(ContractError::MatchBudgetReturned);
+        }
+        
+        let refund = self.sign_refund(pool, sponsor, params.match_budget, get_fee_rate()?, params.max_fee)?;
+        
+        self.match_returned = true;
+        self.total_matched = 0;
+        emit!(ContractEvent::Refunded {
+            recipient: *sponsor,
+            amount: refund.value + refund.fee,
+            txid: refund.txid,
+        });
         
-        Ok(amount)
+        Ok(refund)
+    }
+    
+    /// Withdraw a whole contribution during the contribution phase, refunding
+    /// it like `withdraw`
+    pub fn emergency_withdraw(
+        &mut self,
+        pool: &AccountInfo,
+        record: &mut ContributorRecord,
+    ) -> Result<Refund, ContractError> {
+        let amount = record.amount;
+        self.withdraw(pool, record, amount)
     }
     
     /// Get pool information
@@ -615,108 +1867,790 @@ impl Contract {
         Ok(PoolInfo {
             state: self.state.clone(),
             total_balance: self.total_balance,
-            total_contributors: self.contributions.len() as u64,
-            total_proposals: self.proposals.len() as u64,
-            total_votes: self.votes.len() as u64,
+            total_contributors: self.total_contributors,
+            total_proposals: self.next_proposal_id - 1,
+            total_votes: self.total_votes,
             contribution_deadline: params.contribution_deadline,
+            proposal_deadline: params.proposal_deadline,
             voting_deadline: params.voting_deadline,
+            soft_cap: params.soft_cap,
+            hard_cap: params.hard_cap,
+            progress_percentage: funding_progress(self.total_balance, params),
+            contributions_closed_at: self.contributions_closed_at,
+            match_budget: params.match_budget,
+            total_matched: self.total_matched,
         })
     }
+}
+
+// Percentage of the funding goal raised: the soft cap, or the hard cap if
+// there is no soft cap
+fn funding_progress(total_balance: u64, params: &PoolParams) -> u8 {
+    let goal = if params.soft_cap > 0 { params.soft_cap } else { params.hard_cap };
+    if goal == 0 {
+        return 0;
+    }
+    (total_balance.saturating_mul(100) / goal).min(100) as u8
+}
+
+/// Pool information
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct PoolInfo {
+    pub state: PoolState,
+    #[serde(with = "json::amount")]
+    pub total_balance: u64,
+    pub total_contributors: u64,
+    pub total_proposals: u64,
+    pub total_votes: u64,
+    pub contribution_deadline: i64, // Unix timestamp
+    pub proposal_deadline: i64,     // Unix timestamp
+    pub voting_deadline: i64,       // Unix timestamp
+    #[serde(with = "json::amount")]
+    pub soft_cap: u64,
+    #[serde(with = "json::amount")]
+    pub hard_cap: u64,
+    pub progress_percentage: u8, // Of the soft cap (or hard cap), 0 without either
+    pub contributions_closed_at: Option<i64>, // Unix timestamp
+    #[serde(with = "json::amount")]
+    pub match_budget: u64,
+    #[serde(with = "json::amount")]
+    pub total_matched: u64,
+}
+
+// Move the pool to `to`, emitting `PhaseChanged` if that leaves the current phase
+fn set_phase(state: &mut PoolState, to: PoolState) {
+    if std::mem::discriminant(state) != std::mem::discriminant(&to) {
+        emit!(ContractEvent::PhaseChanged {
+            from: state.clone(),
+            to: to.clone(),
+        });
+    }
+    *state = to;
+}
+
+// Who pays the network fee of a deposit into the pool
+#[derive(Clone, Copy)]
+enum DepositFee {
+    // The deposit, which reaches the pool net of it
+    FromDeposit,
+    // The depositor's change, so the pool receives the whole deposit
+    FromChange,
+}
+
+/// Lowest payout fee rate, in sat/vB, that nodes relay by default
+pub const MIN_FEE_RATE: u64 = 1;
+
+/// Signature size of a taproot key-path spend with the default sighash type
+const TAPROOT_KEY_SPEND_WITNESS_SIZE: usize = 64;
+
+/// Estimate the virtual size of a transaction once its inputs are signed
+pub fn estimate_vsize(transaction: &Transaction) -> u64 {
+    let mut signed = transaction.clone();
+    for input in signed.input.iter_mut().filter(|input| input.witness.is_empty()) {
+        input.witness = vec![vec![0; TAPROOT_KEY_SPEND_WITNESS_SIZE]];
+    }
+    signed.vsize() as u64
+}
+
+/// Pay-to-taproot script locking funds to `key` as an x-only output key
+pub fn taproot_script_pubkey(key: &Pubkey) -> Vec<u8> {
+    let mut script = Vec::with_capacity(34);
+    script.push(0x51); // OP_1
+    script.push(0x20); // Push 32 bytes
+    script.extend_from_slice(key.as_ref());
+    script
+}
+
+// Have the pool sign every input of `transaction`
+fn pool_signatures(transaction: &Transaction, pool: &Pubkey) -> Vec<InputToSign> {
+    (0..transaction.input.len() as u32)
+        .map(|index| InputToSign { index, signer: *pool })
+        .collect()
+}
+
+/// Validate Bitcoin address (simplified)
+fn is_valid_bitcoin_address(address: &str) -> bool {
+    // This is a simplified validation
+    // In a real implementation, this would check the address format and checksum
+    address.starts_with("1") || address.starts_with("3") || address.starts_with("bc1")
+}
+
+// Entrypoint for the program
+entrypoint!(process_instruction);
+
+// Process instruction
+pub fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> Result<(), ProgramError> {
+    // Deserialize instruction data
+    let instruction = ContractInstruction::try_from_slice(instruction_data)
+        .map_err(|_| {
+            msg!("Failed to deserialize instruction data");
+            ProgramError::InvalidInstructionData
+        })?;
+
+    // Process instruction based on type
+    match instruction {
+        ContractInstruction::InitializePool { params, pool_nonce } => {
+            msg!("Instruction: InitializePool");
+            process_initialize_pool(program_id, accounts, params, pool_nonce)
+        }
+        ContractInstruction::Contribute { amount, proof } => {
+            msg!("Instruction: Contribute");
+            process_contribute(program_id, accounts, amount, proof)
+        }
+        ContractInstruction::SubmitProposal { bitcoin_address, metadata } => {
+            msg!("Instruction: SubmitProposal");
+            process_submit_proposal(program_id, accounts, bitcoin_address, metadata)
+        }
+        ContractInstruction::CastVote { proposal_id } => {
+            msg!("Instruction: CastVote");
+            process_cast_vote(program_id, accounts, proposal_id)
+        }
+        ContractInstruction::ExecuteTransfer { fee_rate } => {
+            msg!("Instruction: ExecuteTransfer");
+            process_execute_transfer(program_id, accounts, fee_rate)
+        }
+        ContractInstruction::EmergencyWithdraw => {
+            msg!("Instruction: EmergencyWithdraw");
+            process_emergency_withdraw(program_id, accounts)
+        }
+        ContractInstruction::Withdraw { amount } => {
+            msg!("Instruction: Withdraw");
+            process_withdraw(program_id, accounts, amount)
+        }
+        ContractInstruction::ClaimRefund => {
+            msg!("Instruction: ClaimRefund");
+            process_claim_refund(program_id, accounts)
+        }
+        ContractInstruction::ReclaimMatchBudget => {
+            msg!("Instruction: ReclaimMatchBudget");
+            process_reclaim_match_budget(program_id, accounts)
+        }
+        ContractInstruction::FundProposal { proposal_id, amount } => {
+            msg!("Instruction: FundProposal");
+            process_fund_proposal(program_id, accounts, proposal_id, amount)
+        }
+        ContractInstruction::BumpPayoutFee { new_fee_rate } => {
+            msg!("Instruction: BumpPayoutFee");
+            process_bump_payout_fee(program_id, accounts, new_fee_rate)
+        }
+        ContractInstruction::ConfirmPayout => {
+            msg!("Instruction: ConfirmPayout");
+            process_confirm_payout(program_id, accounts)
+        }
+        ContractInstruction::SettleProposalBond => {
+            msg!("Instruction: SettleProposalBond");
+            process_settle_proposal_bond(program_id, accounts)
+        }
+        ContractInstruction::WithdrawProposal => {
+            msg!("Instruction: WithdrawProposal");
+            process_withdraw_proposal(program_id, accounts)
+        }
+        ContractInstruction::AmendProposal { bitcoin_address, metadata } => {
+            msg!("Instruction: AmendProposal");
+            process_amend_proposal(program_id, accounts, bitcoin_address, metadata)
+        }
+    }
+}
+
+// Contract instructions
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub enum ContractInstruction {
+    /// Accounts: pool, payer (the creator, signer), sponsor (signer, holding the budget) if the pool has a match budget
+    InitializePool { params: PoolParams, pool_nonce: u64 },
+    /// Accounts: pool, contributor (signer), contributor record, payer
+    /// `proof` is empty for public pools
+    Contribute {
+        #[serde(with = "json::amount")]
+        amount: u64,
+        #[serde(with = "json::hex_bytes::vec")]
+        proof: Vec<[u8; 32]>,
+    },
+    /// Accounts: pool, proposer (signer, holding the bond if the pool charges one), proposer's contributor record, new proposal, payer
+    SubmitProposal { bitcoin_address: String, metadata: ProposalMetadata },
+    /// Accounts: pool, voter (signer), voter's contributor record, proposal, payer
+    CastVote { proposal_id: u64 },
+    /// Accounts: pool, creator or winning proposer (signer) if `fee_rate` is set, then the winning
+    /// proposal (winner-take-all) or every proposal in id order (quadratic)
+    ExecuteTransfer { fee_rate: Option<u64> },
+    /// Accounts: pool, contributor (signer), contributor record, payer
+    EmergencyWithdraw,
+    /// Accounts: pool, creator or winning proposer (signer), then the proposals as for `ExecuteTransfer`
+    BumpPayoutFee { new_fee_rate: u64 },
+    /// Accounts: pool, payer
+    ConfirmPayout,
+    /// Accounts: pool, proposer, proposer's contributor record, proposal, payer
+    SettleProposalBond,
+    /// Accounts: pool, proposer (signer), proposer's contributor record, proposal, payer
+    WithdrawProposal,
+    /// Accounts: pool, proposer (signer), proposal, payer
+    AmendProposal { bitcoin_address: String, metadata: ProposalMetadata },
+    /// Accounts: pool, contributor (signer), contributor record, payer
+    Withdraw {
+        #[serde(with = "json::amount")]
+        amount: u64,
+    },
+    /// Accounts: pool, contributor (signer), contributor record, payer
+    ClaimRefund,
+    /// Accounts: pool, sponsor (signer), payer
+    ReclaimMatchBudget,
+    /// Accounts: pool, funder (signer), funder's contributor record, proposal, payer
+    FundProposal {
+        proposal_id: u64,
+        #[serde(with = "json::amount")]
+        amount: u64,
+    },
+}
+
+/// Event emitted by each `Contract` method that changes state, so off-chain
+/// consumers can rebuild a pool without reading its account
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub enum ContractEvent {
+    /// Emitted by the `InitializePool` instruction, the only place that knows
+    /// the pool's derived address
+    PoolInitialized { pool: Pubkey, creator: Pubkey, nonce: u64, params: PoolParams },
+    Contributed { contributor: Pubkey, amount: u64, total_balance: u64 },
+    Withdrawn { contributor: Pubkey, amount: u64, remaining: u64 },
+    ProposalSubmitted {
+        proposal_id: u64,
+        proposer: Pubkey,
+        bitcoin_address: String,
+        metadata: ProposalMetadata,
+        bond: u64,
+    },
+    VoteCast { voter: Pubkey, proposal_id: u64 },
+    PhaseChanged { from: PoolState, to: PoolState },
+    TransferExecuted { txid: [u8; 32], fee_rate: u64, fee: u64, winning_proposal: Option<u64> },
+    /// The payout `replaced` was re-issued as `txid` at a higher fee rate
+    PayoutFeeBumped { txid: [u8; 32], replaced: [u8; 32], fee_rate: u64, fee: u64 },
+    /// One version of the payout, `txid`, is buried deep enough to complete the pool
+    PayoutConfirmed { txid: [u8; 32], confirmations: u32 },
+    /// A contributor's refund from a failed pool, or the sponsor's match budget
+    Refunded { recipient: Pubkey, amount: u64, txid: [u8; 32] },
+    ProposalAmended { proposal_id: u64, bitcoin_address: String, metadata: ProposalMetadata },
+    ProposalWithdrawn { proposal_id: u64 },
+    ProposalFunded { funder: Pubkey, proposal_id: u64, amount: u64 },
+    BondSettled { proposal_id: u64, returned: bool },
+    MatchBudgetDeposited { sponsor: Pubkey, amount: u64 },
+}
+
+// Check that the contract account is the pool's program-derived address
+fn check_pool_address(
+    program_id: &Pubkey,
+    contract_account: &AccountInfo,
+    contract: &Contract,
+) -> Result<(), ProgramError> {
+    if contract.pool.address(program_id)? != *contract_account.key {
+        msg!("Contract account does not match the derived pool address");
+        return Err(ProgramError::InvalidSeeds);
+    }
+    Ok(())
+}
+
+// Check that a record account is owned by the program and sits at its derived address
+fn check_record_address(
+    program_id: &Pubkey,
+    record_account: &AccountInfo,
+    expected: &Pubkey,
+) -> Result<(), ProgramError> {
+    if record_account.owner != program_id {
+        msg!("Record account not owned by program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if record_account.key != expected {
+        msg!("Record account does not match its derived address");
+        return Err(ProgramError::InvalidSeeds);
+    }
+    Ok(())
+}
+
+// Load a contributor's record, or an empty one if they have not contributed yet
+fn load_contributor(
+    program_id: &Pubkey,
+    contract_account: &AccountInfo,
+    contributor: &AccountInfo,
+    record_account: &AccountInfo,
+) -> Result<ContributorRecord, ProgramError> {
+    let (record_address, _) = find_contributor_address(program_id, contract_account.key, contributor.key);
+    check_record_address(program_id, record_account, &record_address)?;
+
+    let record_data = record_account.data.borrow();
+    if record_data.is_empty() {
+        return Ok(ContributorRecord::new(*contributor.key));
+    }
+    match ContributorRecord::try_from_slice(&record_data) {
+        Ok(record) => Ok(record),
+        Err(_) => {
+            msg!("Failed to deserialize contributor record");
+            Err(ProgramError::InvalidInstructionData)
+        }
+    }
+}
+
+// Load a proposal, checking it is stored at its derived address in this pool
+fn load_proposal(
+    program_id: &Pubkey,
+    contract_account: &AccountInfo,
+    proposal_account: &AccountInfo,
+) -> Result<Proposal, ProgramError> {
+    let proposal_data = proposal_account.data.borrow();
+    if proposal_data.is_empty() {
+        return Err(ContractError::ProposalNotFound.into());
+    }
+    let proposal = match Proposal::try_from_slice(&proposal_data) {
+        Ok(proposal) => proposal,
+        Err(_) => {
+            msg!("Failed to deserialize proposal");
+            return Err(ProgramError::InvalidInstructionData);
+        }
+    };
+
+    let (proposal_address, _) = find_proposal_address(program_id, contract_account.key, proposal.id);
+    check_record_address(program_id, proposal_account, &proposal_address)?;
+
+    Ok(proposal)
+}
+
+// Load the remaining accounts as proposals of this pool
+fn load_proposals<'a, 'b: 'a>(
+    program_id: &Pubkey,
+    contract_account: &AccountInfo,
+    proposal_accounts: impl Iterator<Item = &'a AccountInfo<'b>>,
+) -> Result<Vec<Proposal>, ProgramError> {
+    proposal_accounts
+        .map(|proposal_account| load_proposal(program_id, contract_account, proposal_account))
+        .collect()
+}
+
+// Check that an account whose funds or proposal the instruction changes signed it
+fn check_signed(account: &AccountInfo) -> Result<(), ProgramError> {
+    if !account.is_signer {
+        msg!("Account {} must sign", account.key);
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    Ok(())
+}
+
+// Serialize `state` into a program-owned account
+fn save_account<T: BorshSerialize>(account: &AccountInfo, state: &T) -> Result<(), ProgramError> {
+    let data = state.try_to_vec().map_err(ContractError::from)?;
+    *account.data.borrow_mut() = data;
+    Ok(())
+}
+
+// Process initialize pool instruction
+fn process_initialize_pool(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    params: PoolParams,
+    pool_nonce: u64,
+) -> Result<(), ProgramError> {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let payer = next_account_info(account_info_iter)?;
+
+    // Check if the contract account is owned by the program
+    if contract_account.owner != program_id {
+        msg!("Contract account not owned by program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    check_signed(payer)?;
+
+    // A matching budget is deposited by a sponsor, who signs for it
+    let sponsor = if params.match_budget > 0 {
+        let sponsor = next_account_info(account_info_iter)?;
+        check_signed(sponsor)?;
+        Some(sponsor)
+    } else {
+        None
+    };
+
+    // The pool account is derived from its creator (the payer) and nonce
+    let (pool_address, bump) = find_pool_address(program_id, payer.key, pool_nonce);
+    if *contract_account.key != pool_address {
+        msg!("Contract account does not match the derived pool address");
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    // Deserialize contract state or create new if empty
+    let contract_data = contract_account.data.borrow();
+    let mut contract = if !contract_data.is_empty() {
+        match Contract::try_from_slice(&contract_data) {
+            Ok(contract) => contract,
+            Err(_) => {
+                msg!("Failed to deserialize contract state");
+                Contract::default()
+            }
+        }
+    } else {
+        Contract::default()
+    };
+    drop(contract_data);
+
+    // Only the instruction knows which derived pool is being created
+    emit!(ContractEvent::PoolInitialized {
+        pool: pool_address,
+        creator: *payer.key,
+        nonce: pool_nonce,
+        params: params.clone(),
+    });
+
+    // Initialize pool
+    contract.initialize_pool(params).map_err(ProgramError::from)?;
+    contract.pool = PoolId {
+        creator: *payer.key,
+        nonce: pool_nonce,
+        bump,
+    };
+    if let Some(sponsor) = sponsor {
+        let deposited = contract.deposit_match_budget(contract_account, sponsor).map_err(ProgramError::from)?;
+        msg!("Match budget of {} satoshis deposited", deposited);
+    }
+
+    // Serialize and save contract state
+    save_account(contract_account, &contract)?;
+    add_state_transition(payer, program_id, &contract)?;
+
+    Ok(())
+}
+
+// Process contribute instruction
+fn process_contribute(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    proof: Vec<[u8; 32]>,
+) -> Result<(), ProgramError> {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let contributor = next_account_info(account_info_iter)?;
+    let contributor_record = next_account_info(account_info_iter)?;
+    let payer = next_account_info(account_info_iter)?;
+
+    // Check if the contract account is owned by the program
+    if contract_account.owner != program_id {
+        msg!("Contract account not owned by program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    check_signed(contributor)?;
+
+    // Deserialize contract state
+    let contract_data = contract_account.data.borrow();
+    let mut contract = match Contract::try_from_slice(&contract_data) {
+        Ok(contract) => contract,
+        Err(_) => {
+            msg!("Failed to deserialize contract state");
+            return Err(ProgramError::InvalidInstructionData);
+        }
+    };
+    drop(contract_data);
+    check_pool_address(program_id, contract_account, &contract)?;
+
+    let mut record = load_contributor(program_id, contract_account, contributor, contributor_record)?;
+
+    // Contribute from the contributor's UTXO
+    contract.contribute(contract_account, contributor, &mut record, amount, &proof).map_err(ProgramError::from)?;
+
+    // Serialize and save contract state
+    save_account(contract_account, &contract)?;
+    save_account(contributor_record, &record)?;
+    add_state_transition(payer, program_id, &contract)?;
+
+    Ok(())
+}
+
+// Process submit proposal instruction
+fn process_submit_proposal(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    bitcoin_address: String,
+    metadata: ProposalMetadata,
+) -> Result<(), ProgramError> {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let proposer = next_account_info(account_info_iter)?;
+    let proposer_record = next_account_info(account_info_iter)?;
+    let proposal_account = next_account_info(account_info_iter)?;
+    let payer = next_account_info(account_info_iter)?;
+
+    // Check if the contract account is owned by the program
+    if contract_account.owner != program_id {
+        msg!("Contract account not owned by program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    check_signed(proposer)?;
+
+    // Deserialize contract state
+    let contract_data = contract_account.data.borrow();
+    let mut contract = match Contract::try_from_slice(&contract_data) {
+        Ok(contract) => contract,
+        Err(_) => {
+            msg!("Failed to deserialize contract state");
+            return Err(ProgramError::InvalidInstructionData);
+        }
+    };
+    drop(contract_data);
+    check_pool_address(program_id, contract_account, &contract)?;
+
+    let mut record = load_contributor(program_id, contract_account, proposer, proposer_record)?;
+
+    // The new proposal is stored at the address derived from its id
+    let (proposal_address, _) = find_proposal_address(program_id, contract_account.key, contract.next_proposal_id);
+    check_record_address(program_id, proposal_account, &proposal_address)?;
+
+    // Submit proposal
+    let proposal = contract.submit_proposal(contract_account, proposer, &mut record, bitcoin_address, metadata)
+        .map_err(ProgramError::from)?;
+
+    msg!("Proposal submitted with ID: {}", proposal.id);
+    if proposal.bond > 0 {
+        msg!("Bond of {} satoshis deposited", proposal.bond);
+    }
+
+    // Serialize and save contract state
+    save_account(contract_account, &contract)?;
+    save_account(proposer_record, &record)?;
+    save_account(proposal_account, &proposal)?;
+    add_state_transition(payer, program_id, &contract)?;
+
+    Ok(())
+}
+
+// Process cast vote instruction
+fn process_cast_vote(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    proposal_id: u64,
+) -> Result<(), ProgramError> {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let voter = next_account_info(account_info_iter)?;
+    let voter_record = next_account_info(account_info_iter)?;
+    let proposal_account = next_account_info(account_info_iter)?;
+    let payer = next_account_info(account_info_iter)?;
+
+    // Check if the contract account is owned by the program
+    if contract_account.owner != program_id {
+        msg!("Contract account not owned by program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    check_signed(voter)?;
+
+    // Deserialize contract state
+    let contract_data = contract_account.data.borrow();
+    let mut contract = match Contract::try_from_slice(&contract_data) {
+        Ok(contract) => contract,
+        Err(_) => {
+            msg!("Failed to deserialize contract state");
+            return Err(ProgramError::InvalidInstructionData);
+        }
+    };
+    drop(contract_data);
+    check_pool_address(program_id, contract_account, &contract)?;
+
+    let mut record = load_contributor(program_id, contract_account, voter, voter_record)?;
+    let mut proposal = load_proposal(program_id, contract_account, proposal_account)?;
+    if proposal.id != proposal_id {
+        return Err(ContractError::ProposalNotFound.into());
+    }
+
+    // Cast vote
+    contract.cast_vote(&mut record, &mut proposal).map_err(ProgramError::from)?;
+
+    // Serialize and save contract state
+    save_account(contract_account, &contract)?;
+    save_account(voter_record, &record)?;
+    save_account(proposal_account, &proposal)?;
+    add_state_transition(payer, program_id, &contract)?;
+
+    Ok(())
+}
+
+// Process execute transfer instruction
+fn process_execute_transfer(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    fee_rate: Option<u64>,
+) -> Result<(), ProgramError> {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    // Choosing the fee rate takes the signature of someone the fee is paid by
+    let authority = match fee_rate {
+        Some(_) => Some(next_account_info(account_info_iter)?),
+        None => None,
+    };
     
-    /// Get all proposals
-    pub fn get_proposals(&self) -> Vec<Proposal> {
-        self.proposals.values().cloned().collect()
+    // Check if the contract account is owned by the program
+    if contract_account.owner != program_id {
+        msg!("Contract account not owned by program");
+        return Err(ProgramError::IncorrectProgramId);
     }
-    
-    /// Get winning proposal
-    pub fn get_winning_proposal(&self) -> Option<Proposal> {
-        self.winning_proposal.and_then(|id| self.proposals.get(&id).cloned())
+    if let Some(authority) = authority {
+        check_signed(authority)?;
     }
-}
 
-/// Pool information
-#[derive(BorshSerialize, BorshDeserialize, Clone, Debug)]
-pub struct PoolInfo {
-    pub state: PoolState,
-    pub total_balance: u64,
-    pub total_contributors: u64,
-    pub total_proposals: u64,
-    pub total_votes: u64,
-    pub contribution_deadline: i64, // Unix timestamp
-    pub voting_deadline: i64,       // Unix timestamp
-}
+    // Deserialize contract state
+    let contract_data = contract_account.data.borrow();
+    let mut contract = match Contract::try_from_slice(&contract_data) {
+        Ok(contract) => contract,
+        Err(_) => {
+            msg!("Failed to deserialize contract state");
+            return Err(ProgramError::InvalidInstructionData);
+        }
+    };
+    drop(contract_data);
+    check_pool_address(program_id, contract_account, &contract)?;
 
-/// Validate Bitcoin address (simplified)
-fn is_valid_bitcoin_address(address: &str) -> bool {
-    // This is a simplified validation
-    // In a real implementation, this would check the address format and checksum
-    address.starts_with("1") || address.starts_with("3") || address.starts_with("bc1")
-}
+    let proposals = load_proposals(program_id, contract_account, account_info_iter)?;
 
-// Entrypoint for the program
-entrypoint!(process_instruction);
+    // Execute transfer
+    contract.execute_transfer(program_id, accounts, authority.map(|authority| authority.key), &proposals, fee_rate)
+        .map_err(ProgramError::from)?;
+    save_account(contract_account, &contract)?;
 
-// Process instruction
-pub fn process_instruction(
+    msg!("Transfer executed successfully");
+
+    Ok(())
+}
+
+// Process emergency withdraw instruction
+fn process_emergency_withdraw(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    instruction_data: &[u8],
 ) -> Result<(), ProgramError> {
-    // Deserialize instruction data
-    let instruction = ContractInstruction::try_from_slice(instruction_data)
-        .map_err(|_| {
-            msg!("Failed to deserialize instruction data");
-            ProgramError::InvalidInstructionData
-        })?;
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let contributor = next_account_info(account_info_iter)?;
+    let contributor_record = next_account_info(account_info_iter)?;
+    let payer = next_account_info(account_info_iter)?;
 
-    // Process instruction based on type
-    match instruction {
-        ContractInstruction::InitializePool { params } => {
-            msg!("Instruction: InitializePool");
-            process_initialize_pool(program_id, accounts, params)
-        }
-        ContractInstruction::Contribute { amount } => {
-            msg!("Instruction: Contribute");
-            process_contribute(program_id, accounts, amount)
-        }
-        ContractInstruction::SubmitProposal { bitcoin_address, description } => {
-            msg!("Instruction: SubmitProposal");
-            process_submit_proposal(program_id, accounts, bitcoin_address, description)
-        }
-        ContractInstruction::CastVote { proposal_id } => {
-            msg!("Instruction: CastVote");
-            process_cast_vote(program_id, accounts, proposal_id)
-        }
-        ContractInstruction::ExecuteTransfer => {
-            msg!("Instruction: ExecuteTransfer");
-            process_execute_transfer(program_id, accounts)
-        }
-        ContractInstruction::EmergencyWithdraw => {
-            msg!("Instruction: EmergencyWithdraw");
-            process_emergency_withdraw(program_id, accounts)
+    // Check if the contract account is owned by the program
+    if contract_account.owner != program_id {
+        msg!("Contract account not owned by program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    check_signed(contributor)?;
+
+    // Deserialize contract state
+    let contract_data = contract_account.data.borrow();
+    let mut contract = match Contract::try_from_slice(&contract_data) {
+        Ok(contract) => contract,
+        Err(_) => {
+            msg!("Failed to deserialize contract state");
+            return Err(ProgramError::InvalidInstructionData);
         }
+    };
+    drop(contract_data);
+    check_pool_address(program_id, contract_account, &contract)?;
+
+    let mut record = load_contributor(program_id, contract_account, contributor, contributor_record)?;
+
+    // Emergency withdraw
+    let refund = contract.emergency_withdraw(contract_account, &mut record).map_err(ProgramError::from)?;
+
+    msg!("Emergency withdrawal of {} satoshis refunded ({} fee)", refund.value, refund.fee);
+
+    // Serialize and save contract state
+    save_account(contract_account, &contract)?;
+    save_account(contributor_record, &record)?;
+    add_state_transition(payer, program_id, &contract)?;
+
+    Ok(())
+}
+
+// Process bump payout fee instruction
+fn process_bump_payout_fee(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_fee_rate: u64,
+) -> Result<(), ProgramError> {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let authority = next_account_info(account_info_iter)?;
+
+    // Check if the contract account is owned by the program
+    if contract_account.owner != program_id {
+        msg!("Contract account not owned by program");
+        return Err(ProgramError::IncorrectProgramId);
     }
+    check_signed(authority)?;
+
+    // Deserialize contract state
+    let contract_data = contract_account.data.borrow();
+    let mut contract = match Contract::try_from_slice(&contract_data) {
+        Ok(contract) => contract,
+        Err(_) => {
+            msg!("Failed to deserialize contract state");
+            return Err(ProgramError::InvalidInstructionData);
+        }
+    };
+    drop(contract_data);
+    check_pool_address(program_id, contract_account, &contract)?;
+
+    let proposals = load_proposals(program_id, contract_account, account_info_iter)?;
+
+    // Replace the pending payout
+    contract.bump_payout_fee(program_id, accounts, authority.key, &proposals, new_fee_rate)
+        .map_err(ProgramError::from)?;
+    save_account(contract_account, &contract)?;
+
+    msg!("Payout fee bumped to {} sat/vB", new_fee_rate);
+
+    Ok(())
 }
 
-// Contract instructions
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
-pub enum ContractInstruction {
-    InitializePool { params: PoolParams },
-    Contribute { amount: u64 },
-    SubmitProposal { bitcoin_address: String, description: String },
-    CastVote { proposal_id: u64 },
-    ExecuteTransfer,
-    EmergencyWithdraw,
+// Process confirm payout instruction
+fn process_confirm_payout(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> Result<(), ProgramError> {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let payer = next_account_info(account_info_iter)?;
+
+    // Check if the contract account is owned by the program
+    if contract_account.owner != program_id {
+        msg!("Contract account not owned by program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Deserialize contract state
+    let contract_data = contract_account.data.borrow();
+    let mut contract = match Contract::try_from_slice(&contract_data) {
+        Ok(contract) => contract,
+        Err(_) => {
+            msg!("Failed to deserialize contract state");
+            return Err(ProgramError::InvalidInstructionData);
+        }
+    };
+    drop(contract_data);
+    check_pool_address(program_id, contract_account, &contract)?;
+
+    // Confirm payout
+    let confirmations = contract.confirm_payout().map_err(ProgramError::from)?;
+
+    msg!("Payout confirmed with {} confirmations", confirmations);
+
+    // Serialize and save contract state
+    save_account(contract_account, &contract)?;
+    add_state_transition(payer, program_id, &contract)?;
+
+    Ok(())
 }
 
-// Process initialize pool instruction
-fn process_initialize_pool(
+// Process settle proposal bond instruction
+fn process_settle_proposal_bond(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    params: PoolParams,
 ) -> Result<(), ProgramError> {
     let account_info_iter = &mut accounts.iter();
     let contract_account = next_account_info(account_info_iter)?;
+    let proposer = next_account_info(account_info_iter)?;
+    let proposer_record = next_account_info(account_info_iter)?;
+    let proposal_account = next_account_info(account_info_iter)?;
     let payer = next_account_info(account_info_iter)?;
 
     // Check if the contract account is owned by the program
@@ -725,39 +2659,49 @@ fn process_initialize_pool(
         return Err(ProgramError::IncorrectProgramId);
     }
 
-    // Deserialize contract state or create new if empty
-    let mut contract_data = contract_account.data.borrow();
-    let mut contract = if contract_data.len() > 0 {
-        match Contract::try_from_slice(&contract_data) {
-            Ok(contract) => contract,
-            Err(_) => {
-                msg!("Failed to deserialize contract state");
-                Contract::default()
-            }
+    // Deserialize contract state
+    let contract_data = contract_account.data.borrow();
+    let mut contract = match Contract::try_from_slice(&contract_data) {
+        Ok(contract) => contract,
+        Err(_) => {
+            msg!("Failed to deserialize contract state");
+            return Err(ProgramError::InvalidInstructionData);
         }
-    } else {
-        Contract::default()
     };
     drop(contract_data);
+    check_pool_address(program_id, contract_account, &contract)?;
 
-    // Initialize pool
-    contract.initialize_pool(params).map_err(|e| e.into())?;
+    let mut record = load_contributor(program_id, contract_account, proposer, proposer_record)?;
+    let mut proposal = load_proposal(program_id, contract_account, proposal_account)?;
+
+    // Settle bond
+    let refund = contract.settle_proposal_bond(contract_account, &mut record, &mut proposal)
+        .map_err(ProgramError::from)?;
+
+    match refund {
+        Some(refund) => msg!("Bond of proposal {} refunded ({} fee)", proposal.id, refund.fee),
+        None => msg!("Bond of proposal {} slashed into the payout", proposal.id),
+    }
 
     // Serialize and save contract state
+    save_account(contract_account, &contract)?;
+    save_account(proposer_record, &record)?;
+    save_account(proposal_account, &proposal)?;
     add_state_transition(payer, program_id, &contract)?;
 
     Ok(())
 }
 
-// Process contribute instruction
-fn process_contribute(
+// Process withdraw proposal instruction
+fn process_withdraw_proposal(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    amount: u64,
 ) -> Result<(), ProgramError> {
     let account_info_iter = &mut accounts.iter();
     let contract_account = next_account_info(account_info_iter)?;
-    let contributor = next_account_info(account_info_iter)?;
+    let proposer = next_account_info(account_info_iter)?;
+    let proposer_record = next_account_info(account_info_iter)?;
+    let proposal_account = next_account_info(account_info_iter)?;
     let payer = next_account_info(account_info_iter)?;
 
     // Check if the contract account is owned by the program
@@ -765,9 +2709,10 @@ fn process_contribute(
         msg!("Contract account not owned by program");
         return Err(ProgramError::IncorrectProgramId);
     }
+    check_signed(proposer)?;
 
     // Deserialize contract state
-    let mut contract_data = contract_account.data.borrow();
+    let contract_data = contract_account.data.borrow();
     let mut contract = match Contract::try_from_slice(&contract_data) {
         Ok(contract) => contract,
         Err(_) => {
@@ -776,26 +2721,84 @@ fn process_contribute(
         }
     };
     drop(contract_data);
+    check_pool_address(program_id, contract_account, &contract)?;
+
+    let mut record = load_contributor(program_id, contract_account, proposer, proposer_record)?;
+    let mut proposal = load_proposal(program_id, contract_account, proposal_account)?;
 
-    // Contribute
-    contract.contribute(*contributor.key, amount).map_err(|e| e.into())?;
+    // Withdraw proposal
+    let refund = contract.withdraw_proposal(contract_account, &mut record, &mut proposal)
+        .map_err(ProgramError::from)?;
+
+    msg!("Proposal {} withdrawn", proposal.id);
+    if let Some(refund) = refund {
+        msg!("Bond of {} satoshis refunded ({} fee)", refund.value, refund.fee);
+    }
 
     // Serialize and save contract state
+    save_account(contract_account, &contract)?;
+    save_account(proposer_record, &record)?;
+    save_account(proposal_account, &proposal)?;
     add_state_transition(payer, program_id, &contract)?;
 
     Ok(())
 }
 
-// Process submit proposal instruction
-fn process_submit_proposal(
+// Process amend proposal instruction
+fn process_amend_proposal(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
     bitcoin_address: String,
-    description: String,
+    metadata: ProposalMetadata,
 ) -> Result<(), ProgramError> {
     let account_info_iter = &mut accounts.iter();
     let contract_account = next_account_info(account_info_iter)?;
     let proposer = next_account_info(account_info_iter)?;
+    let proposal_account = next_account_info(account_info_iter)?;
+
+    // Check if the contract account is owned by the program
+    if contract_account.owner != program_id {
+        msg!("Contract account not owned by program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    check_signed(proposer)?;
+
+    // Deserialize contract state
+    let contract_data = contract_account.data.borrow();
+    let contract = match Contract::try_from_slice(&contract_data) {
+        Ok(contract) => contract,
+        Err(_) => {
+            msg!("Failed to deserialize contract state");
+            return Err(ProgramError::InvalidInstructionData);
+        }
+    };
+    drop(contract_data);
+    check_pool_address(program_id, contract_account, &contract)?;
+
+    let mut proposal = load_proposal(program_id, contract_account, proposal_account)?;
+
+    // Amend proposal
+    contract.amend_proposal(proposer.key, &mut proposal, bitcoin_address, metadata)
+        .map_err(ProgramError::from)?;
+
+    msg!("Proposal {} amended ({} amendments)", proposal.id, proposal.amendments.len());
+
+    // Serialize and save proposal
+    save_account(proposal_account, &proposal)?;
+
+    Ok(())
+}
+
+// Process withdraw instruction
+fn process_withdraw(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> Result<(), ProgramError> {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let contributor = next_account_info(account_info_iter)?;
+    let contributor_record = next_account_info(account_info_iter)?;
     let payer = next_account_info(account_info_iter)?;
 
     // Check if the contract account is owned by the program
@@ -803,9 +2806,10 @@ fn process_submit_proposal(
         msg!("Contract account not owned by program");
         return Err(ProgramError::IncorrectProgramId);
     }
+    check_signed(contributor)?;
 
     // Deserialize contract state
-    let mut contract_data = contract_account.data.borrow();
+    let contract_data = contract_account.data.borrow();
     let mut contract = match Contract::try_from_slice(&contract_data) {
         Ok(contract) => contract,
         Err(_) => {
@@ -814,28 +2818,32 @@ fn process_submit_proposal(
         }
     };
     drop(contract_data);
+    check_pool_address(program_id, contract_account, &contract)?;
 
-    // Submit proposal
-    let proposal_id = contract.submit_proposal(*proposer.key, bitcoin_address, description)
-        .map_err(|e| e.into())?;
+    let mut record = load_contributor(program_id, contract_account, contributor, contributor_record)?;
+
+    // Withdraw
+    let refund = contract.withdraw(contract_account, &mut record, amount).map_err(ProgramError::from)?;
 
-    msg!("Proposal submitted with ID: {}", proposal_id);
+    msg!("Withdrawal of {} satoshis refunded ({} fee)", refund.value, refund.fee);
 
     // Serialize and save contract state
+    save_account(contract_account, &contract)?;
+    save_account(contributor_record, &record)?;
     add_state_transition(payer, program_id, &contract)?;
 
     Ok(())
 }
 
-// Process cast vote instruction
-fn process_cast_vote(
+// Process claim refund instruction
+fn process_claim_refund(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    proposal_id: u64,
 ) -> Result<(), ProgramError> {
     let account_info_iter = &mut accounts.iter();
     let contract_account = next_account_info(account_info_iter)?;
-    let voter = next_account_info(account_info_iter)?;
+    let contributor = next_account_info(account_info_iter)?;
+    let contributor_record = next_account_info(account_info_iter)?;
     let payer = next_account_info(account_info_iter)?;
 
     // Check if the contract account is owned by the program
@@ -843,9 +2851,10 @@ fn process_cast_vote(
         msg!("Contract account not owned by program");
         return Err(ProgramError::IncorrectProgramId);
     }
+    check_signed(contributor)?;
 
     // Deserialize contract state
-    let mut contract_data = contract_account.data.borrow();
+    let contract_data = contract_account.data.borrow();
     let mut contract = match Contract::try_from_slice(&contract_data) {
         Ok(contract) => contract,
         Err(_) => {
@@ -854,32 +2863,42 @@ fn process_cast_vote(
         }
     };
     drop(contract_data);
+    check_pool_address(program_id, contract_account, &contract)?;
 
-    // Cast vote
-    contract.cast_vote(*voter.key, proposal_id).map_err(|e| e.into())?;
+    let mut record = load_contributor(program_id, contract_account, contributor, contributor_record)?;
+
+    // Claim refund
+    let refund = contract.claim_refund(contract_account, &mut record).map_err(ProgramError::from)?;
+
+    msg!("Refund of {} satoshis issued ({} fee)", refund.value, refund.fee);
 
     // Serialize and save contract state
+    save_account(contract_account, &contract)?;
+    save_account(contributor_record, &record)?;
     add_state_transition(payer, program_id, &contract)?;
 
     Ok(())
 }
 
-// Process execute transfer instruction
-fn process_execute_transfer(
+// Process reclaim match budget instruction
+fn process_reclaim_match_budget(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
 ) -> Result<(), ProgramError> {
     let account_info_iter = &mut accounts.iter();
     let contract_account = next_account_info(account_info_iter)?;
-    
+    let sponsor = next_account_info(account_info_iter)?;
+    let payer = next_account_info(account_info_iter)?;
+
     // Check if the contract account is owned by the program
     if contract_account.owner != program_id {
         msg!("Contract account not owned by program");
         return Err(ProgramError::IncorrectProgramId);
     }
+    check_signed(sponsor)?;
 
     // Deserialize contract state
-    let mut contract_data = contract_account.data.borrow();
+    let contract_data = contract_account.data.borrow();
     let mut contract = match Contract::try_from_slice(&contract_data) {
         Ok(contract) => contract,
         Err(_) => {
@@ -888,23 +2907,32 @@ fn process_execute_transfer(
         }
     };
     drop(contract_data);
+    check_pool_address(program_id, contract_account, &contract)?;
 
-    // Execute transfer
-    contract.execute_transfer(program_id, accounts).map_err(|e| e.into())?;
+    // Return the match budget
+    let refund = contract.reclaim_match_budget(contract_account, sponsor.key).map_err(ProgramError::from)?;
 
-    msg!("Transfer executed successfully");
+    msg!("Match budget of {} satoshis returned ({} fee)", refund.value, refund.fee);
+
+    // Serialize and save contract state
+    save_account(contract_account, &contract)?;
+    add_state_transition(payer, program_id, &contract)?;
 
     Ok(())
 }
 
-// Process emergency withdraw instruction
-fn process_emergency_withdraw(
+// Process fund proposal instruction
+fn process_fund_proposal(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
+    proposal_id: u64,
+    amount: u64,
 ) -> Result<(), ProgramError> {
     let account_info_iter = &mut accounts.iter();
     let contract_account = next_account_info(account_info_iter)?;
-    let contributor = next_account_info(account_info_iter)?;
+    let funder = next_account_info(account_info_iter)?;
+    let funder_record = next_account_info(account_info_iter)?;
+    let proposal_account = next_account_info(account_info_iter)?;
     let payer = next_account_info(account_info_iter)?;
 
     // Check if the contract account is owned by the program
@@ -912,9 +2940,10 @@ fn process_emergency_withdraw(
         msg!("Contract account not owned by program");
         return Err(ProgramError::IncorrectProgramId);
     }
+    check_signed(funder)?;
 
     // Deserialize contract state
-    let mut contract_data = contract_account.data.borrow();
+    let contract_data = contract_account.data.borrow();
     let mut contract = match Contract::try_from_slice(&contract_data) {
         Ok(contract) => contract,
         Err(_) => {
@@ -923,13 +2952,23 @@ fn process_emergency_withdraw(
         }
     };
     drop(contract_data);
+    check_pool_address(program_id, contract_account, &contract)?;
 
-    // Emergency withdraw
-    let amount = contract.emergency_withdraw(*contributor.key).map_err(|e| e.into())?;
+    let mut record = load_contributor(program_id, contract_account, funder, funder_record)?;
+    let mut proposal = load_proposal(program_id, contract_account, proposal_account)?;
+    if proposal.id != proposal_id {
+        return Err(ContractError::ProposalNotFound.into());
+    }
+
+    // Direct the contribution
+    contract.fund_proposal(&mut record, &mut proposal, amount).map_err(ProgramError::from)?;
 
-    msg!("Emergency withdrawal of {} satoshis successful", amount);
+    msg!("Directed {} satoshis to proposal {}", amount, proposal_id);
 
     // Serialize and save contract state
+    save_account(contract_account, &contract)?;
+    save_account(funder_record, &record)?;
+    save_account(proposal_account, &proposal)?;
     add_state_transition(payer, program_id, &contract)?;
 
     Ok(())
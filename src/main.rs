@@ -1,79 +1,270 @@
-use arch_program::{
-    account::AccountInfo,
-    bitcoin::{self, absolute::LockTime, transaction::Version, Transaction},
-    entrypoint,
-    helper::add_state_transition,
-    input_to_sign::InputToSign,
-    msg,
-    program::{
-        get_account_script_pubkey, get_bitcoin_block_height, next_account_info,
-        set_transaction_to_sign,
-    },
-    program_error::ProgramError,
-    pubkey::Pubkey,
-    transaction_to_sign::TransactionToSign,
-};
+// The program itself lives in lib.rs; this binary holds offline tooling around it.
+use arch_network_contract::replay::{replay, ReplayLog};
+use arch_network_contract::simulation::{simulate, Scenario};
+use arch_network_contract::json::{self, AmountFormat};
+use arch_network_contract::{find_pool_address, Contract, ContractInstruction};
+use arch_program::pubkey::Pubkey;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use borsh::{BorshDeserialize, BorshSerialize};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(version, about = "Offline tooling for the Arch Network funding pool")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// How JSON output writes satoshi amounts
+    #[arg(long, global = true, value_enum, default_value_t = Amounts::Number)]
+    amounts: Amounts,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Encode a JSON instruction as instruction data
+    Encode {
+        /// The instruction as JSON, e.g. '{"CastVote":{"proposal_id":1}}', or - for stdin
+        instruction: String,
+        #[arg(long, value_enum, default_value_t = Encoding::Hex)]
+        format: Encoding,
+    },
+    /// Decode instruction data and print it as JSON
+    Decode {
+        /// The instruction data, or - for stdin
+        data: String,
+        #[arg(long, value_enum, default_value_t = Encoding::Hex)]
+        format: Encoding,
+    },
+    /// Print a pool account dump as JSON
+    Contract {
+        /// File holding the account data, or - for stdin
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = Encoding::Raw)]
+        format: Encoding,
+    },
+    /// Print the pool info of a pool account dump as JSON
+    Info {
+        /// File holding the account data, or - for stdin
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = Encoding::Raw)]
+        format: Encoding,
+        /// Program id (base58 or 64 hex digits), to also print the pool's address
+        #[arg(long)]
+        program_id: Option<Pubkey>,
+    },
+    /// Replay a JSON instruction log and print what each step changed
+    Replay {
+        /// Path to the instruction log, or - for stdin
+        file: PathBuf,
+    },
+    /// Run a YAML or JSON pool scenario and print the pool after each step
+    Simulate {
+        /// Path to the scenario, or - for stdin
+        file: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Amounts {
+    Number,
+    /// Decimal strings, for consumers whose numbers lose precision above 2^53
+    String,
+}
+
+/// How binary data is written as text
+#[derive(Clone, Copy, ValueEnum)]
+enum Encoding {
+    Hex,
+    Base64,
+    /// Bytes as they are, for files and pipes
+    Raw,
+}
+
+impl Encoding {
+    fn encode(self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            Encoding::Hex => hex::encode(bytes).into_bytes(),
+            Encoding::Base64 => BASE64.encode(bytes).into_bytes(),
+            Encoding::Raw => bytes.to_vec(),
+        }
+    }
+
+    fn decode(self, input: &[u8]) -> Result<Vec<u8>, String> {
+        let text = || String::from_utf8_lossy(input).trim().to_string();
+        match self {
+            Encoding::Hex => hex::decode(text()).map_err(|error| format!("Invalid hex: {}", error)),
+            Encoding::Base64 => BASE64.decode(text()).map_err(|error| format!("Invalid base64: {}", error)),
+            Encoding::Raw => Ok(input.to_vec()),
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let amounts = match cli.amounts {
+        Amounts::Number => AmountFormat::Number,
+        Amounts::String => AmountFormat::String,
+    };
+    let result = match cli.command {
+        Command::Encode { instruction, format } => run_encode(&instruction, format),
+        Command::Decode { data, format } => run_decode(&data, format, amounts),
+        Command::Contract { file, format } => {
+            load_contract(&file, format).and_then(|contract| print_json(&contract, amounts))
+        }
+        Command::Info { file, format, program_id } => run_info(&file, format, program_id, amounts),
+        Command::Replay { file } => run_replay(&file, amounts),
+        Command::Simulate { file } => run_simulate(&file, amounts),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run_encode(json: &str, format: Encoding) -> Result<(), String> {
+    let json = if json == "-" { read_input(&PathBuf::from("-"))? } else { json.as_bytes().to_vec() };
+    let instruction: ContractInstruction =
+        serde_json::from_slice(&json).map_err(|error| format!("Invalid instruction: {}", error))?;
+    let data = instruction.try_to_vec().map_err(|error| error.to_string())?;
+    write_output(&format.encode(&data), format)
+}
+
+fn run_decode(data: &str, format: Encoding, amounts: AmountFormat) -> Result<(), String> {
+    let input = if data == "-" { read_input(&PathBuf::from("-"))? } else { data.as_bytes().to_vec() };
+    let instruction = ContractInstruction::try_from_slice(&format.decode(&input)?)
+        .map_err(|error| format!("Invalid instruction data: {}", error))?;
+    print_json(&instruction, amounts)
+}
 
-// Main program entry point
-entrypoint!(process_instruction);
-
-// Contract instructions
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
-pub enum ContractInstruction {
-    InitializePool { params: PoolParams },
-    Contribute { amount: u64 },
-    SubmitProposal { bitcoin_address: String, description: String },
-    CastVote { proposal_id: u64 },
-    ExecuteTransfer,
-    EmergencyWithdraw,
+fn run_info(file: &PathBuf, format: Encoding, program_id: Option<Pubkey>, amounts: AmountFormat) -> Result<(), String> {
+    let contract = load_contract(file, format)?;
+    let info = contract.get_pool_info().map_err(|error| format!("{:?}", error))?;
+    let mut json = json::to_value(&info, amounts).map_err(|error| error.to_string())?;
+    if let Some(program_id) = program_id {
+        let (address, _) = find_pool_address(&program_id, &contract.pool.creator, contract.pool.nonce);
+        json["address"] = serde_json::to_value(address).map_err(|error| error.to_string())?;
+    }
+    print_json(&json, amounts)
 }
 
-// Process instruction
-pub fn process_instruction(
-    program_id: &Pubkey,
-    accounts: &[AccountInfo],
-    instruction_data: &[u8],
-) -> Result<(), ProgramError> {
-    // Deserialize instruction data
-    let instruction = ContractInstruction::try_from_slice(instruction_data)
-        .map_err(|_| {
-            msg!("Failed to deserialize instruction data");
-            ProgramError::InvalidInstructionData
-        })?;
-
-    // Process instruction based on type
-    match instruction {
-        ContractInstruction::InitializePool { params } => {
-            msg!("Instruction: InitializePool");
-            process_initialize_pool(program_id, accounts, params)
+fn run_replay(file: &PathBuf, amounts: AmountFormat) -> Result<(), String> {
+    let json = String::from_utf8(read_input(file)?).map_err(|error| error.to_string())?;
+    let (program_id, accounts, steps) = ReplayLog::from_json(&json)?.decode()?;
+    let report = replay(program_id, &accounts, &steps);
+
+    for (step, outcome) in steps.iter().zip(&report.steps) {
+        let status = match &outcome.result {
+            Ok(()) => "ok".to_string(),
+            Err(error) => format!("failed: {}", error),
+        };
+        println!(
+            "Step {} at {}: {:?} ({}, {} compute units)",
+            outcome.index, outcome.timestamp, step.instruction, status, outcome.compute_units
+        );
+        for change in &outcome.changes {
+            println!("  {}: {} -> {}", change.field, change.before, change.after);
+        }
+        for event in &outcome.events {
+            println!("  event {:?}", event);
+        }
+    }
+
+    match &report.final_state {
+        Some(state) => {
+            println!("Final state:");
+            print_json(state, amounts)
         }
-        ContractInstruction::Contribute { amount } => {
-            msg!("Instruction: Contribute");
-            process_contribute(program_id, accounts, amount)
+        None => {
+            println!("Final state: pool not initialized");
+            Ok(())
         }
-        ContractInstruction::SubmitProposal { bitcoin_address, description } => {
-            msg!("Instruction: SubmitProposal");
-            process_submit_proposal(program_id, accounts, bitcoin_address, description)
+    }
+}
+
+fn run_simulate(file: &PathBuf, amounts: AmountFormat) -> Result<(), String> {
+    let text = String::from_utf8(read_input(file)?).map_err(|error| error.to_string())?;
+    let scenario = Scenario::parse(&text)?;
+    let report = simulate(&scenario);
+
+    if let Err(error) = &report.initialization {
+        return Err(format!("Pool failed to initialize: {}", error));
+    }
+    for outcome in &report.steps {
+        let elapsed = outcome.timestamp - scenario.start;
+        let step = serde_json::to_string(&outcome.step).map_err(|error| error.to_string())?;
+        match &outcome.result {
+            Ok(()) => println!("[+{}s] {}", elapsed, step),
+            Err(error) => println!("[+{}s] {} failed: {}", elapsed, step, error),
         }
-        ContractInstruction::CastVote { proposal_id } => {
-            msg!("Instruction: CastVote");
-            process_cast_vote(program_id, accounts, proposal_id)
+        for event in &outcome.events {
+            println!("  event {:?}", event);
         }
-        ContractInstruction::ExecuteTransfer => {
-            msg!("Instruction: ExecuteTransfer");
-            process_execute_transfer(program_id, accounts)
+        if let Some(state) = &outcome.state {
+            let phase = serde_json::to_string(&state.state).map_err(|error| error.to_string())?;
+            println!(
+                "  {}: balance {}, {} contributors, {} votes, leading {:?}",
+                phase, state.total_balance, state.total_contributors, state.total_votes, state.leading_proposal
+            );
         }
-        ContractInstruction::EmergencyWithdraw => {
-            msg!("Instruction: EmergencyWithdraw");
-            process_emergency_withdraw(program_id, accounts)
+    }
+
+    println!();
+    match report.winner() {
+        Some(winner) => println!("Winner: proposal {} ({}, {} votes)", winner.id, winner.bitcoin_address, winner.votes),
+        None => println!("Winner: none"),
+    }
+    match &report.payout {
+        Some(payout) => {
+            println!("Payout transaction {}:", hex::encode(payout.transaction.compute_txid().0));
+            for output in &payout.transaction.output {
+                println!("  {} sat to {}", output.value, hex::encode(&output.script_pubkey));
+            }
         }
+        None => println!("Payout transaction: none"),
+    }
+    if let Some(state) = &report.final_state {
+        println!("Final state:");
+        print_json(state, amounts)?;
+    }
+    Ok(())
+}
+
+fn load_contract(file: &PathBuf, format: Encoding) -> Result<Contract, String> {
+    let data = format.decode(&read_input(file)?)?;
+    Contract::try_from_slice(&data).map_err(|error| format!("Invalid pool account: {}", error))
+}
+
+// Read a file, or stdin for `-`
+fn read_input(file: &PathBuf) -> Result<Vec<u8>, String> {
+    let mut input = Vec::new();
+    if file.as_os_str() == "-" {
+        std::io::stdin().read_to_end(&mut input).map_err(|error| error.to_string())?;
+    } else {
+        input = std::fs::read(file).map_err(|error| format!("Cannot read {}: {}", file.display(), error))?;
     }
+    Ok(input)
 }
 
-// Import all the necessary types and functions from lib.rs
-use crate::{
-    Contract, ContractError, PoolParams, PoolState, Proposal, PoolInfo,
-    process_initialize_pool, process_contribute, process_submit_proposal,
-    process_cast_vote, process_execute_transfer, process_emergency_withdraw
-};
+// Write encoded output, ending text encodings with a newline
+fn write_output(bytes: &[u8], format: Encoding) -> Result<(), String> {
+    use std::io::Write;
+
+    let mut stdout = std::io::stdout();
+    stdout.write_all(bytes).map_err(|error| error.to_string())?;
+    if !matches!(format, Encoding::Raw) {
+        writeln!(stdout).map_err(|error| error.to_string())?;
+    }
+    Ok(())
+}
+
+fn print_json<T: Serialize>(value: &T, amounts: AmountFormat) -> Result<(), String> {
+    let json = json::to_string_pretty(value, amounts).map_err(|error| error.to_string())?;
+    println!("{}", json);
+    Ok(())
+}
@@ -0,0 +1,310 @@
+//! Replay of a recorded instruction log against a fresh or dumped pool
+//!
+//! Each step runs at its recorded time on the simulated runtime, so the same
+//! log always produces the same state. The report lists what each step changed
+//! in the pool account, which makes it easy to compare against the state of a
+//! deployed pool.
+
+use crate::{process_instruction, simulation::DEPOSIT_FEE_ALLOWANCE, Contract, ContractEvent, ContractInstruction};
+use arch_program::{
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    program_test::ProgramTest,
+    pubkey::Pubkey,
+    runtime::{self, Account, Log},
+};
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::Deserialize;
+
+/// A recorded instruction and the time it ran at
+#[derive(Clone, Debug)]
+pub struct ReplayStep {
+    pub timestamp: i64, // Unix timestamp
+    pub block_height: Option<u32>,
+    pub instruction: ContractInstruction,
+    pub accounts: Vec<AccountMeta>, // The pool account first, as for every instruction
+}
+
+/// An account as it was before the first step, such as a dump of a deployed pool
+#[derive(Clone, Debug)]
+pub struct InitialAccount {
+    pub pubkey: Pubkey,
+    pub data: Vec<u8>,
+}
+
+/// A pool field a step changed, formatted with `Debug`
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub before: String,
+    pub after: String,
+}
+
+/// Outcome of one replayed step
+#[derive(Clone, Debug)]
+pub struct StepReport {
+    pub index: usize,
+    pub timestamp: i64,
+    pub result: Result<(), ProgramError>,
+    pub changes: Vec<FieldChange>,
+    pub events: Vec<ContractEvent>,
+    pub compute_units: u64,
+}
+
+/// Outcome of a whole replay
+#[derive(Clone, Debug)]
+pub struct ReplayReport {
+    pub pool: Option<Pubkey>,
+    pub final_state: Option<Contract>,
+    pub steps: Vec<StepReport>,
+}
+
+/// Replay `steps` in order against a fresh runtime holding `accounts`
+///
+/// `accounts` are owned by the program, so a log can start from a dump of the
+/// pool rather than from its initialization; the first step's changes are then
+/// relative to the dumped state. Other accounts are created empty and owned by
+/// the program the first time a step uses them. A log does not record the
+/// UTXOs contributions were deposited from, so each contributor is backed with
+/// one holding the contribution plus `DEPOSIT_FEE_ALLOWANCE` before their
+/// step. A failing step is reported and rolled back, and the replay carries on
+/// with the next one.
+pub fn replay(program_id: Pubkey, accounts: &[InitialAccount], steps: &[ReplayStep]) -> ReplayReport {
+    let mut test = ProgramTest::new(program_id, process_instruction);
+    for account in accounts {
+        test.add_account(account.pubkey, Account {
+            owner: program_id,
+            data: account.data.clone(),
+            ..Account::default()
+        });
+    }
+    let pool = steps.first().and_then(|step| step.accounts.first()).map(|meta| meta.pubkey);
+    let mut state = pool.and_then(|pool| test.account_data::<Contract>(&pool));
+    let mut reports = Vec::new();
+
+    for (index, step) in steps.iter().enumerate() {
+        for meta in &step.accounts {
+            if test.account(&meta.pubkey).is_none() {
+                test.add_program_account(meta.pubkey);
+            }
+        }
+        if let (ContractInstruction::Contribute { amount, .. }, Some(contributor)) = (&step.instruction, step.accounts.get(1)) {
+            test.fund_account(contributor.pubkey, amount + DEPOSIT_FEE_ALLOWANCE);
+        }
+        runtime::set_unix_timestamp(step.timestamp);
+        if let Some(height) = step.block_height {
+            runtime::set_block_height(height);
+        }
+
+        let instruction = Instruction::new_with_borsh(program_id, &step.instruction, step.accounts.clone());
+        let invocations = runtime::invocation_logs().len();
+        let result = test.process_transaction(&[instruction]);
+        let events = events_since(invocations);
+        let compute_units = runtime::compute_units().iter().skip(invocations).sum();
+
+        let after = pool.and_then(|pool| test.account_data::<Contract>(&pool));
+        reports.push(StepReport {
+            index,
+            timestamp: step.timestamp,
+            result,
+            changes: diff(state.as_ref(), after.as_ref()),
+            events,
+            compute_units,
+        });
+        state = after;
+    }
+
+    ReplayReport {
+        pool,
+        final_state: state,
+        steps: reports,
+    }
+}
+
+/// Contract events of the invocations after the first `invocations`
+pub(crate) fn events_since(invocations: usize) -> Vec<ContractEvent> {
+    runtime::invocation_logs()
+        .iter()
+        .skip(invocations)
+        .flatten()
+        .filter_map(|entry| match entry {
+            Log::Data(data) => ContractEvent::try_from_slice(data).ok(),
+            Log::Message(_) => None,
+        })
+        .collect()
+}
+
+/// Fields that differ between two versions of the pool state
+pub fn diff(before: Option<&Contract>, after: Option<&Contract>) -> Vec<FieldChange> {
+    let before = before.map(contract_fields).unwrap_or_default();
+    let after = after.map(contract_fields).unwrap_or_default();
+
+    let mut changes = Vec::new();
+    for (field, value) in &after {
+        let previous = before.iter().find(|(name, _)| name == field).map(|(_, value)| value.clone());
+        if previous.as_ref() != Some(value) {
+            changes.push(FieldChange {
+                field,
+                before: previous.unwrap_or_default(),
+                after: value.clone(),
+            });
+        }
+    }
+    changes
+}
+
+fn contract_fields(contract: &Contract) -> Vec<(&'static str, String)> {
+    vec![
+        ("state", format!("{:?}", contract.state)),
+        ("params", format!("{:?}", contract.params)),
+        ("pool", format!("{:?}", contract.pool)),
+        ("total_balance", format!("{:?}", contract.total_balance)),
+        ("total_contributors", format!("{:?}", contract.total_contributors)),
+        ("total_votes", format!("{:?}", contract.total_votes)),
+        ("next_proposal_id", format!("{:?}", contract.next_proposal_id)),
+        ("leading_proposal", format!("{:?}", contract.leading_proposal)),
+        ("leading_votes", format!("{:?}", contract.leading_votes)),
+        ("winning_proposal", format!("{:?}", contract.winning_proposal)),
+        ("transfer_executed", format!("{:?}", contract.transfer_executed)),
+        ("pending_payout", format!("{:?}", contract.pending_payout)),
+        ("slashed_bonds", format!("{:?}", contract.slashed_bonds)),
+        ("total_bonds", format!("{:?}", contract.total_bonds)),
+        ("contributions_closed_at", format!("{:?}", contract.contributions_closed_at)),
+        ("sponsor", format!("{:?}", contract.sponsor)),
+        ("total_matched", format!("{:?}", contract.total_matched)),
+        ("match_returned", format!("{:?}", contract.match_returned)),
+        ("pool_utxo", format!("{:?}", contract.pool_utxo)),
+        ("spent_account_utxo", format!("{:?}", contract.spent_account_utxo)),
+    ]
+}
+
+/// Instruction log file, as JSON
+///
+/// ```json
+/// {
+///   "program_id": "<base58 or 64 hex digits>",
+///   "accounts": [{ "pubkey": "<base58 or 64 hex digits>", "data": "<hex of the account data>" }],
+///   "steps": [
+///     {
+///       "timestamp": 1700000000,
+///       "instruction": "<hex of the Borsh-encoded ContractInstruction>",
+///       "accounts": [{ "pubkey": "<base58 or 64 hex digits>", "is_signer": false, "is_writable": true }]
+///     }
+///   ]
+/// }
+/// ```
+///
+/// `accounts` is optional and seeds the runtime before the first step.
+#[derive(Deserialize)]
+pub struct ReplayLog {
+    pub program_id: String,
+    #[serde(default)]
+    pub accounts: Vec<ReplayLogInitialAccount>,
+    pub steps: Vec<ReplayLogStep>,
+}
+
+#[derive(Deserialize)]
+pub struct ReplayLogInitialAccount {
+    pub pubkey: String,
+    pub data: String,
+}
+
+#[derive(Deserialize)]
+pub struct ReplayLogStep {
+    pub timestamp: i64,
+    #[serde(default)]
+    pub block_height: Option<u32>,
+    pub instruction: String,
+    pub accounts: Vec<ReplayLogAccount>,
+}
+
+#[derive(Deserialize)]
+pub struct ReplayLogAccount {
+    pub pubkey: String,
+    #[serde(default)]
+    pub is_signer: bool,
+    #[serde(default = "writable")]
+    pub is_writable: bool,
+}
+
+fn writable() -> bool {
+    true
+}
+
+impl ReplayLog {
+    /// Parse a JSON instruction log
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|error| format!("Invalid replay log: {}", error))
+    }
+
+    /// Decode the program id, initial accounts and steps
+    pub fn decode(&self) -> Result<(Pubkey, Vec<InitialAccount>, Vec<ReplayStep>), String> {
+        let program_id = parse_pubkey(&self.program_id)?;
+        let accounts = self
+            .accounts
+            .iter()
+            .map(|account| {
+                Ok(InitialAccount {
+                    pubkey: parse_pubkey(&account.pubkey)?,
+                    data: hex::decode(&account.data)
+                        .map_err(|error| format!("Account {}: invalid data hex: {}", account.pubkey, error))?,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        let steps = self
+            .steps
+            .iter()
+            .enumerate()
+            .map(|(index, step)| {
+                let data = hex::decode(&step.instruction)
+                    .map_err(|error| format!("Step {}: invalid instruction hex: {}", index, error))?;
+                let instruction = ContractInstruction::try_from_slice(&data)
+                    .map_err(|error| format!("Step {}: invalid instruction: {}", index, error))?;
+                let accounts = step
+                    .accounts
+                    .iter()
+                    .map(|account| {
+                        Ok(AccountMeta {
+                            pubkey: parse_pubkey(&account.pubkey)?,
+                            is_signer: account.is_signer,
+                            is_writable: account.is_writable,
+                        })
+                    })
+                    .collect::<Result<_, String>>()?;
+                Ok(ReplayStep {
+                    timestamp: step.timestamp,
+                    block_height: step.block_height,
+                    instruction,
+                    accounts,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok((program_id, accounts, steps))
+    }
+}
+
+/// Build the log entry for an initial account, the inverse of `ReplayLog::decode`
+pub fn encode_account(account: &InitialAccount) -> serde_json::Value {
+    serde_json::json!({
+        "pubkey": account.pubkey.to_string(),
+        "data": hex::encode(&account.data),
+    })
+}
+
+/// Build the log entry for a step, the inverse of `ReplayLog::decode`
+pub fn encode_step(step: &ReplayStep) -> serde_json::Value {
+    serde_json::json!({
+        "timestamp": step.timestamp,
+        "block_height": step.block_height,
+        "instruction": hex::encode(step.instruction.try_to_vec().expect("Instruction should serialize")),
+        "accounts": step.accounts.iter().map(|meta| serde_json::json!({
+            "pubkey": meta.pubkey.to_string(),
+            "is_signer": meta.is_signer,
+            "is_writable": meta.is_writable,
+        })).collect::<Vec<_>>(),
+    })
+}
+
+fn parse_pubkey(text: &str) -> Result<Pubkey, String> {
+    text.parse().map_err(|error| format!("Invalid pubkey {}: {}", text, error))
+}
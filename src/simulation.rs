@@ -0,0 +1,347 @@
+//! Simulation of a pool design from a scenario file
+//!
+//! A scenario gives the pool parameters and a timeline of what participants
+//! do. Each entry runs as an instruction against the simulated runtime, with
+//! the clock moved only by `advance` entries, so a pool design can be tried
+//! out before it is launched.
+
+use crate::replay::events_since;
+use crate::{
+    find_contributor_address, find_pool_address, find_proposal_address, process_instruction, Contract,
+    ContractEvent, ContractInstruction, PoolParams, Proposal, ProposalMetadata,
+};
+use arch_program::{
+    bitcoin::Txid,
+    instruction::{AccountMeta, Instruction},
+    program::sha256,
+    program_error::ProgramError,
+    program_test::ProgramTest,
+    pubkey::Pubkey,
+    runtime,
+    transaction_to_sign::TransactionToSign,
+};
+use serde::{Deserialize, Serialize};
+
+/// Clock time a scenario starts at unless it sets `start`
+pub const DEFAULT_START: i64 = 1_700_000_000;
+
+/// Satoshis a contributor holds on top of each contribution to pay the
+/// network fee of its deposit
+pub const DEPOSIT_FEE_ALLOWANCE: u64 = 10_000;
+
+/// A pool design and a timeline to run it through
+///
+/// Deadlines in `params` are seconds after `start`. Participants are named;
+/// each name stands for the same key throughout the scenario.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default = "default_start")]
+    pub start: i64, // Unix timestamp
+    #[serde(default = "default_creator")]
+    pub creator: String,
+    #[serde(default = "default_sponsor")]
+    pub sponsor: String, // Signs for the match budget, if the pool has one
+    pub params: PoolParams,
+    pub steps: Vec<Step>,
+}
+
+fn default_start() -> i64 {
+    DEFAULT_START
+}
+
+fn default_creator() -> String {
+    "creator".to_string()
+}
+
+fn default_sponsor() -> String {
+    "sponsor".to_string()
+}
+
+/// An entry of a scenario's timeline
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Step {
+    /// Move the clock forward by this many seconds
+    Advance(i64),
+    Contribute { contributor: String, amount: u64 },
+    Withdraw { contributor: String, amount: u64 },
+    Propose {
+        proposer: String,
+        bitcoin_address: String,
+        #[serde(default)]
+        metadata: ProposalMetadata,
+    },
+    Vote { voter: String, proposal_id: u64 },
+    Fund { funder: String, proposal_id: u64, amount: u64 },
+    WithdrawProposal { proposer: String, proposal_id: u64 },
+    Execute {
+        #[serde(default)]
+        fee_rate: Option<u64>,
+    },
+    /// Confirm the payout this many blocks deep (the pool's required
+    /// confirmations by default), then complete the pool
+    Confirm {
+        #[serde(default)]
+        blocks: Option<u32>,
+    },
+    SettleBond { proposer: String, proposal_id: u64 },
+    ClaimRefund { contributor: String },
+    ReclaimMatch,
+}
+
+impl Scenario {
+    /// Parse a scenario written in YAML or JSON
+    pub fn parse(text: &str) -> Result<Self, String> {
+        // Going through a JSON value lets YAML write steps as plain maps
+        // (`- vote: { ... }`) rather than tagged values (`- !vote { ... }`)
+        let value: serde_json::Value =
+            serde_yaml::from_str(text).map_err(|error| format!("Invalid scenario: {}", error))?;
+        serde_json::from_value(value).map_err(|error| format!("Invalid scenario: {}", error))
+    }
+}
+
+/// Outcome of one timeline entry
+#[derive(Clone, Debug)]
+pub struct StepOutcome {
+    pub step: Step,
+    pub timestamp: i64,
+    pub result: Result<(), ProgramError>,
+    pub events: Vec<ContractEvent>,
+    pub state: Option<Contract>, // Pool state after the step
+}
+
+/// Outcome of a whole scenario
+#[derive(Clone, Debug)]
+pub struct SimulationReport {
+    pub pool: Pubkey,
+    pub initialization: Result<(), ProgramError>, // The `InitializePool` the scenario starts with
+    pub steps: Vec<StepOutcome>,
+    pub final_state: Option<Contract>,
+    pub proposals: Vec<Proposal>,
+    pub payout: Option<TransactionToSign>, // Latest payout signed, confirmed or not
+}
+
+impl SimulationReport {
+    /// The proposal the pool paid out to, if it picked a winner
+    pub fn winner(&self) -> Option<&Proposal> {
+        let id = self.final_state.as_ref()?.winning_proposal?;
+        self.proposals.iter().find(|proposal| proposal.id == id)
+    }
+}
+
+/// Key standing for the participant `name`
+pub fn participant(name: &str) -> Pubkey {
+    Pubkey::from_bytes(sha256(&[b"simulation", name.as_bytes()]))
+}
+
+/// Run `scenario` against a fresh runtime
+///
+/// A failing entry is reported and rolled back, and the timeline carries on.
+pub fn simulate(scenario: &Scenario) -> SimulationReport {
+    let program_id = participant("program");
+    let mut test = ProgramTest::new(program_id, process_instruction);
+    runtime::set_unix_timestamp(scenario.start);
+
+    let mut params = scenario.params.clone();
+    params.contribution_deadline += scenario.start;
+    params.proposal_deadline += scenario.start;
+    params.voting_deadline += scenario.start;
+
+    let creator = participant(&scenario.creator);
+    let (pool, _) = find_pool_address(&program_id, &creator, 0);
+    test.add_program_account(pool);
+    let mut accounts = vec![AccountMeta::new(pool, false), AccountMeta::new(creator, true)];
+    if params.match_budget > 0 {
+        // The sponsor brings the budget as a UTXO of their own
+        let sponsor = participant(&scenario.sponsor);
+        accounts.push(AccountMeta::new_readonly(sponsor, true));
+        test.fund_account(sponsor, params.match_budget);
+    }
+    let instruction = ContractInstruction::InitializePool { params, pool_nonce: 0 };
+    let initialization = run(&mut test, &instruction, accounts);
+
+    let mut simulation = Simulation {
+        test,
+        pool,
+        creator,
+        sponsor: participant(&scenario.sponsor),
+    };
+    let steps: Vec<StepOutcome> = scenario
+        .steps
+        .iter()
+        .map(|step| {
+            let invocations = runtime::invocation_logs().len();
+            let result = simulation.step(step);
+            StepOutcome {
+                step: step.clone(),
+                timestamp: runtime::unix_timestamp(),
+                result,
+                events: events_since(invocations),
+                state: simulation.contract(),
+            }
+        })
+        .collect();
+
+    let final_state = simulation.contract();
+    let proposals = simulation.proposals();
+    let payout_txid = steps.iter().flat_map(|outcome: &StepOutcome| &outcome.events).rev().find_map(|event| match event {
+        ContractEvent::TransferExecuted { txid, .. } | ContractEvent::PayoutFeeBumped { txid, .. } => Some(Txid(*txid)),
+        _ => None,
+    });
+    let payout = payout_txid.and_then(|txid| {
+        runtime::transactions_to_sign()
+            .into_iter()
+            .find(|transaction| transaction.transaction.compute_txid() == txid)
+    });
+    SimulationReport {
+        pool,
+        initialization,
+        steps,
+        final_state,
+        proposals,
+        payout,
+    }
+}
+
+struct Simulation {
+    test: ProgramTest,
+    pool: Pubkey,
+    creator: Pubkey,
+    sponsor: Pubkey,
+}
+
+impl Simulation {
+    fn program_id(&self) -> Pubkey {
+        self.test.program_id()
+    }
+
+    fn contract(&self) -> Option<Contract> {
+        self.test.account_data(&self.pool)
+    }
+
+    fn proposals(&self) -> Vec<Proposal> {
+        let next = self.contract().map_or(1, |contract| contract.next_proposal_id);
+        (1..next).filter_map(|id| self.test.account_data(&self.proposal(id))).collect()
+    }
+
+    fn record(&mut self, contributor: &Pubkey) -> Pubkey {
+        let (record, _) = find_contributor_address(&self.program_id(), &self.pool, contributor);
+        if self.test.account(&record).is_none() {
+            self.test.add_program_account(record);
+        }
+        record
+    }
+
+    fn proposal(&self, id: u64) -> Pubkey {
+        find_proposal_address(&self.program_id(), &self.pool, id).0
+    }
+
+    // Pool, signer, the signer's contributor record, then `rest` and the payer
+    fn signed(&mut self, name: &str, rest: &[AccountMeta]) -> Vec<AccountMeta> {
+        let key = participant(name);
+        let record = self.record(&key);
+        let mut accounts = vec![
+            AccountMeta::new(self.pool, false),
+            AccountMeta::new_readonly(key, true),
+            AccountMeta::new(record, false),
+        ];
+        accounts.extend_from_slice(rest);
+        accounts.push(AccountMeta::new_readonly(key, true));
+        accounts
+    }
+
+    // The pool, then every proposal in id order
+    fn with_proposals(&self) -> Vec<AccountMeta> {
+        let next = self.contract().map_or(1, |contract| contract.next_proposal_id);
+        let mut accounts = vec![AccountMeta::new(self.pool, false)];
+        accounts.extend((1..next).map(|id| AccountMeta::new_readonly(self.proposal(id), false)));
+        accounts
+    }
+
+    fn step(&mut self, step: &Step) -> Result<(), ProgramError> {
+        let pool = self.pool;
+        let (instruction, accounts) = match step {
+            Step::Advance(seconds) => {
+                runtime::advance_time(*seconds);
+                return Ok(());
+            }
+            Step::Contribute { contributor, amount } => {
+                // The contributor brings the contribution and the deposit fee
+                // as a UTXO of their own, and gets the rest back as change
+                self.test.fund_account(participant(contributor), amount + DEPOSIT_FEE_ALLOWANCE);
+                let instruction = ContractInstruction::Contribute { amount: *amount, proof: Vec::new() };
+                (instruction, self.signed(contributor, &[]))
+            }
+            Step::Withdraw { contributor, amount } => {
+                (ContractInstruction::Withdraw { amount: *amount }, self.signed(contributor, &[]))
+            }
+            Step::Propose { proposer, bitcoin_address, metadata } => {
+                let contract = self.contract();
+                let id = contract.as_ref().map_or(1, |contract| contract.next_proposal_id);
+                let proposal = self.proposal(id);
+                self.test.add_program_account(proposal);
+                // The proposer brings the bond as a UTXO of their own
+                let bond = contract.and_then(|contract| contract.params).map_or(0, |params| params.proposal_bond);
+                if bond > 0 {
+                    self.test.fund_account(participant(proposer), bond);
+                }
+                let instruction = ContractInstruction::SubmitProposal {
+                    bitcoin_address: bitcoin_address.clone(),
+                    metadata: metadata.clone(),
+                };
+                (instruction, self.signed(proposer, &[AccountMeta::new(proposal, false)]))
+            }
+            Step::Vote { voter, proposal_id } => {
+                let proposal = AccountMeta::new(self.proposal(*proposal_id), false);
+                (ContractInstruction::CastVote { proposal_id: *proposal_id }, self.signed(voter, &[proposal]))
+            }
+            Step::Fund { funder, proposal_id, amount } => {
+                let proposal = AccountMeta::new(self.proposal(*proposal_id), false);
+                let instruction = ContractInstruction::FundProposal { proposal_id: *proposal_id, amount: *amount };
+                (instruction, self.signed(funder, &[proposal]))
+            }
+            Step::WithdrawProposal { proposer, proposal_id } => {
+                let proposal = AccountMeta::new(self.proposal(*proposal_id), false);
+                (ContractInstruction::WithdrawProposal, self.signed(proposer, &[proposal]))
+            }
+            Step::SettleBond { proposer, proposal_id } => {
+                let proposal = AccountMeta::new(self.proposal(*proposal_id), false);
+                (ContractInstruction::SettleProposalBond, self.signed(proposer, &[proposal]))
+            }
+            Step::ClaimRefund { contributor } => (ContractInstruction::ClaimRefund, self.signed(contributor, &[])),
+            Step::Execute { fee_rate } => {
+                let mut accounts = self.with_proposals();
+                if fee_rate.is_some() {
+                    // The creator signs for a chosen fee rate
+                    accounts.insert(1, AccountMeta::new_readonly(self.creator, true));
+                }
+                (ContractInstruction::ExecuteTransfer { fee_rate: *fee_rate }, accounts)
+            }
+            Step::Confirm { blocks } => {
+                let contract = self.contract();
+                if let Some(payout) = contract.as_ref().and_then(|contract| contract.pending_payout.as_ref()) {
+                    let required = contract.as_ref().and_then(|contract| contract.params.as_ref());
+                    let depth = blocks.unwrap_or(required.map_or(1, |params| params.required_confirmations));
+                    runtime::set_transaction_confirmations(Txid(payout.txid), depth);
+                }
+                let accounts = vec![AccountMeta::new(pool, false), AccountMeta::new_readonly(self.creator, true)];
+                (ContractInstruction::ConfirmPayout, accounts)
+            }
+            Step::ReclaimMatch => {
+                let accounts = vec![
+                    AccountMeta::new(pool, false),
+                    AccountMeta::new_readonly(self.sponsor, true),
+                    AccountMeta::new_readonly(self.sponsor, true),
+                ];
+                (ContractInstruction::ReclaimMatchBudget, accounts)
+            }
+        };
+        run(&mut self.test, &instruction, accounts)
+    }
+}
+
+fn run(test: &mut ProgramTest, instruction: &ContractInstruction, accounts: Vec<AccountMeta>) -> Result<(), ProgramError> {
+    let program_id = test.program_id();
+    test.process_transaction(&[Instruction::new_with_borsh(program_id, instruction, accounts)])
+}